@@ -0,0 +1,201 @@
+use sdl2::rect::Rect;
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData};
+use crate::animation::{advance_frames, Animation, AnimationSettings};
+use crate::bounce::{BounceCurve, BounceSettings};
+use crate::layers::LayerImage;
+use crate::particles::emit_for_action;
+use crate::sounds::play_sounds_for_action;
+use crate::transform::{avatar_pivot, avatar_transform, copy_transformed};
+
+/// A one-shot action (wave, nod, jump, etc.), which plays for a while and then goes back
+/// to whatever the voice is doing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ActionSettings {
+    pub(crate) name: String,
+    // SDL key name, as given by Keycode::name(). Empty if unbound.
+    pub(crate) hotkey: String,
+    // in milliseconds
+    pub(crate) duration: f32,
+    // Shown instead of the avatar while the action plays, or empty to only bounce
+    pub(crate) texture_path: String,
+    pub(crate) animation: AnimationSettings,
+    pub(crate) bounce: BounceSettings,
+}
+
+impl Default for ActionSettings {
+    fn default() -> Self {
+        ActionSettings {
+            name: String::from("Action"),
+            hotkey: String::new(),
+            duration: 1000.0,
+            texture_path: String::new(),
+            animation: AnimationSettings::default(),
+            bounce: BounceSettings {
+                height: -64.0,
+                duration: 500.0,
+                curve: BounceCurve::Sine
+            }
+        }
+    }
+}
+
+pub struct Action<'a> {
+    pub(crate) settings: ActionSettings,
+    pub(crate) image: Option<LayerImage<'a>>,
+    pub(crate) animation: Option<Animation<'a>>,
+}
+
+impl Action<'_> {
+    pub fn load(data: &SharedData, settings: &ActionSettings) -> Action<'static> {
+        let mut action = Action {
+            settings: settings.clone(),
+            image: None,
+            animation: None
+        };
+
+        action.reload_image(data);
+        action
+    }
+
+    pub fn reload_image(&mut self, data: &SharedData) {
+        unsafe {
+            self.destroy();
+        }
+
+        let path = self.settings.texture_path.as_str();
+
+        if path.is_empty() {
+            self.image = None;
+            self.animation = None;
+        } else {
+            self.image = Some(LayerImage::load(data, path));
+            self.animation = Animation::load(data, path, &self.settings.animation);
+        }
+    }
+
+    pub unsafe fn destroy(&self) {
+        if let Some(image) = &self.image {
+            image.destroy();
+        }
+
+        if let Some(animation) = &self.animation {
+            animation.destroy();
+        }
+    }
+}
+
+pub struct ActionState {
+    pub(crate) current: Option<usize>,
+    time: f32,
+    frame: usize,
+    frame_time: f32,
+}
+
+impl ActionState {
+    pub fn new() -> ActionState {
+        ActionState {
+            current: None,
+            time: 0.0,
+            frame: 0,
+            frame_time: 0.0
+        }
+    }
+}
+
+pub fn find_action(data: &SharedData, name: &str) -> Option<usize> {
+    data.actions.iter().position(|action| action.settings.name.eq_ignore_ascii_case(name))
+}
+
+/// Starts an action from the beginning, cutting off whichever one was already playing.
+pub fn start_action(data: &mut SharedData, index: usize) {
+    if index >= data.actions.len() {
+        return;
+    }
+
+    data.action_state = ActionState {
+        current: Some(index),
+        ..ActionState::new()
+    };
+
+    let name = data.actions[index].settings.name.clone();
+    emit_for_action(data, name.as_str());
+    play_sounds_for_action(data, name.as_str());
+
+    data.requires_update = true;
+}
+
+fn current_action<'a>(data: &'a SharedData) -> Option<&'a Action<'static>> {
+    data.action_state.current.and_then(|index| data.actions.get(index))
+}
+
+/// The extra vertical offset from the current action's bounce.
+pub fn action_offset(data: &SharedData) -> f64 {
+    match current_action(data) {
+        Some(action) if data.action_state.time < action.settings.bounce.duration => action.settings.bounce.offset(data.action_state.time),
+        _ => 0.0
+    }
+}
+
+pub fn tick_actions(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+
+    let (duration, animation, looping) = match current_action(data) {
+        Some(action) => (action.settings.duration, action.animation.as_ref().map(|animation| animation as *const Animation), action.settings.animation.looping),
+        None => return
+    };
+
+    let state = &mut data.action_state;
+    state.time += millis;
+
+    if state.time >= duration {
+        // Back to normal, this frame draws the avatar again.
+        data.action_state = ActionState::new();
+        data.requires_update = true;
+        return;
+    }
+
+    if let Some(animation) = animation {
+        unsafe {
+            advance_frames(&*animation, looping, &mut state.frame, &mut state.frame_time, millis);
+        }
+    }
+
+    // The bounce (if there is one) moves every frame anyway.
+    data.requires_update = true;
+}
+
+/// Draws the action's image in place of the avatar, returning false if there isn't one to show.
+pub unsafe fn render_action(window_size: (u32, u32), data: &SharedData) -> bool {
+    let action = match current_action(data) {
+        Some(action) => action,
+        None => return false
+    };
+
+    let (texture, source, width, height) = match (&action.animation, &action.image) {
+        (Some(animation), _) => match animation.frame(data.action_state.frame) {
+            Some((texture, frame)) => (texture, frame.source, frame.width, frame.height),
+            None => return false
+        },
+        (None, Some(image)) => (&image.texture, None, image.texture_surface.width(), image.texture_surface.height()),
+        (None, None) => return false
+    };
+
+    let height_reduction = match data.current_timing {
+        Some(timing) => (*timing).height_reduction,
+        None => 0
+    };
+
+    let window_height = window_size.1 - (height_reduction as u32);
+    let height_percent = (window_height as f64) / (height as f64);
+    let new_width = ((width as f64) * height_percent) as i32;
+
+    let x = ((window_size.0 as i32) / 2) - (new_width / 2);
+    let y = (window_size.1 - window_height) as i32;
+    let dest = Rect::new(x, y, new_width as u32, window_height);
+
+    copy_transformed(data.pngtuber_canvas, texture, source, dest, &avatar_transform(data), avatar_pivot(window_size, data));
+
+    true
+}
@@ -0,0 +1,404 @@
+use std::fs::File;
+use std::io::BufReader;
+use png::{BlendOp, ColorType, DisposeOp, Transformations};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::Texture;
+use sdl2::surface::Surface;
+use sdl2_sys::SDL_DestroyTexture;
+use serde::{Deserialize, Serialize};
+use crate::{load_image, NANOS_TO_MILLIS, SharedData};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AnimationSettings {
+    pub(crate) looping: bool,
+    // Sprite sheets are only used if the grid is bigger than 1x1.
+    pub(crate) sheet_columns: i32,
+    pub(crate) sheet_rows: i32,
+    // 0 uses every cell in the grid
+    pub(crate) sheet_frames: i32,
+    pub(crate) sheet_fps: f32,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        AnimationSettings {
+            looping: true,
+            sheet_columns: 1,
+            sheet_rows: 1,
+            sheet_frames: 0,
+            sheet_fps: 12.0
+        }
+    }
+}
+
+impl AnimationSettings {
+    pub fn is_sprite_sheet(&self) -> bool {
+        self.sheet_columns > 1 || self.sheet_rows > 1
+    }
+}
+
+pub struct AnimationFrame {
+    pub(crate) texture_index: usize,
+    pub(crate) source: Option<Rect>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    // in milliseconds
+    pub(crate) delay: f32,
+}
+
+pub struct Animation<'a> {
+    pub(crate) surfaces: Vec<Surface<'a>>,
+    pub(crate) textures: Vec<Texture>,
+    pub(crate) frames: Vec<AnimationFrame>,
+}
+
+// Fully composited RGBA frames, along with their delay in milliseconds.
+type DecodedFrames = (u32, u32, Vec<(Vec<u8>, f32)>);
+
+impl Animation<'_> {
+    /// Loads the image at the given path as an animation, if it is one.
+    /// Static images (and anything that fails to decode) return None, so the timing's
+    /// regular texture gets used instead.
+    pub fn load(data: &SharedData, texture_path: &str, settings: &AnimationSettings) -> Option<Animation<'static>> {
+        if texture_path.is_empty() {
+            return None;
+        }
+
+        if settings.is_sprite_sheet() {
+            return Some(load_sprite_sheet(data, texture_path, settings));
+        }
+
+        let lower_path = texture_path.to_lowercase();
+        let decoded = if lower_path.ends_with(".gif") {
+            decode_gif(texture_path)
+        } else if lower_path.ends_with(".png") || lower_path.ends_with(".apng") {
+            decode_apng(texture_path)
+        } else {
+            return None;
+        };
+
+        match decoded {
+            Ok(Some((width, height, frames))) => Some(create_from_frames(data, width, height, frames)),
+            Ok(None) => None,
+            Err(err) => {
+                eprintln!("Failed to decode animation {}: {}", texture_path, err);
+                None
+            }
+        }
+    }
+
+    pub fn frame(&self, index: usize) -> Option<(&Texture, &AnimationFrame)> {
+        if self.frames.is_empty() {
+            return None;
+        }
+
+        let frame = &self.frames[index.min(self.frames.len() - 1)];
+        Some((&self.textures[frame.texture_index], frame))
+    }
+
+    pub unsafe fn destroy(&self) {
+        for surface in self.surfaces.iter() {
+            drop(surface.context());
+        }
+
+        for texture in self.textures.iter() {
+            SDL_DestroyTexture(texture.raw());
+        }
+    }
+}
+
+fn load_sprite_sheet(data: &SharedData, texture_path: &str, settings: &AnimationSettings) -> Animation<'static> {
+    let (surface, texture) = load_image(data, texture_path);
+
+    let columns = settings.sheet_columns.max(1) as u32;
+    let rows = settings.sheet_rows.max(1) as u32;
+    let cell_width = surface.width() / columns;
+    let cell_height = surface.height() / rows;
+
+    let mut total_frames = columns * rows;
+    if settings.sheet_frames > 0 {
+        total_frames = total_frames.min(settings.sheet_frames as u32);
+    }
+
+    let delay = if settings.sheet_fps > 0.0 {
+        1000.0 / settings.sheet_fps
+    } else {
+        f32::INFINITY
+    };
+
+    let mut frames = Vec::new();
+    for i in 0..total_frames {
+        frames.push(AnimationFrame {
+            texture_index: 0,
+            source: Some(Rect::new(((i % columns) * cell_width) as i32, ((i / columns) * cell_height) as i32, cell_width, cell_height)),
+            width: cell_width,
+            height: cell_height,
+            delay
+        });
+    }
+
+    Animation {
+        surfaces: vec![surface],
+        textures: vec![texture],
+        frames
+    }
+}
+
+fn create_from_frames(data: &SharedData, width: u32, height: u32, decoded: Vec<(Vec<u8>, f32)>) -> Animation<'static> {
+    let mut animation = Animation {
+        surfaces: Vec::new(),
+        textures: Vec::new(),
+        frames: Vec::new()
+    };
+
+    for (i, (pixels, delay)) in decoded.iter().enumerate() {
+        let mut surface = Surface::new(width, height, PixelFormatEnum::RGBA32).unwrap();
+        let pitch = surface.pitch() as usize;
+        let row_size = (width * 4) as usize;
+
+        surface.with_lock_mut(|buffer| {
+            for y in 0..(height as usize) {
+                buffer[(y * pitch)..(y * pitch + row_size)].copy_from_slice(&pixels[(y * row_size)..((y + 1) * row_size)]);
+            }
+        });
+
+        let texture = unsafe { (*data.pngtuber_canvas).create_texture_from_surface(&surface).unwrap() };
+
+        animation.surfaces.push(surface);
+        animation.textures.push(texture);
+        animation.frames.push(AnimationFrame {
+            texture_index: i,
+            source: None,
+            width,
+            height,
+            delay: *delay
+        });
+    }
+
+    animation
+}
+
+fn decode_gif(texture_path: &str) -> Result<Option<DecodedFrames>, String> {
+    let file = File::open(texture_path).map_err(|e| e.to_string())?;
+
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(BufReader::new(file)).map_err(|e| e.to_string())?;
+
+    let width = decoder.width() as u32;
+    let height = decoder.height() as u32;
+    let mut canvas = vec![0u8; (width * height * 4) as usize];
+    let mut frames = Vec::new();
+
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| e.to_string())? {
+        let previous = if frame.dispose == gif::DisposalMethod::Previous {
+            Some(canvas.clone())
+        } else {
+            None
+        };
+
+        let region = (frame.left as u32, frame.top as u32, frame.width as u32, frame.height as u32);
+        blit(&mut canvas, width, height, &frame.buffer, region, true);
+
+        // Most programs treat a delay of 0 or 1 as 100ms, so do the same here.
+        let delay = if frame.delay <= 1 {
+            100.0
+        } else {
+            (frame.delay as f32) * 10.0
+        };
+
+        frames.push((canvas.clone(), delay));
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => clear(&mut canvas, width, height, region),
+            gif::DisposalMethod::Previous => canvas = previous.unwrap(),
+            _ => {}
+        }
+    }
+
+    if frames.len() <= 1 {
+        return Ok(None);
+    }
+
+    Ok(Some((width, height, frames)))
+}
+
+fn decode_apng(texture_path: &str) -> Result<Option<DecodedFrames>, String> {
+    let file = File::open(texture_path).map_err(|e| e.to_string())?;
+
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(Transformations::normalize_to_color8() | Transformations::ALPHA);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+
+    let num_frames = match reader.info().animation_control() {
+        Some(control) => control.num_frames,
+        None => return Ok(None) // just a regular png
+    };
+
+    // If there's no fcTL before the IDAT, the default image isn't part of the animation.
+    let default_is_frame = reader.info().frame_control().is_some();
+    let total_images = if default_is_frame { num_frames } else { num_frames + 1 };
+
+    let (width, height) = reader.info().size();
+    let mut buffer = vec![0u8; reader.output_buffer_size()];
+    let mut canvas = vec![0u8; (width * height * 4) as usize];
+    let mut frames = Vec::new();
+
+    for i in 0..total_images {
+        let output = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+
+        if i == 0 && !default_is_frame {
+            continue;
+        }
+
+        let control = *reader.info().frame_control().unwrap();
+        let (color_type, _) = reader.output_color_type();
+        let pixels = to_rgba(&buffer[..output.buffer_size()], color_type);
+
+        let previous = if control.dispose_op == DisposeOp::Previous {
+            Some(canvas.clone())
+        } else {
+            None
+        };
+
+        let region = (control.x_offset, control.y_offset, control.width, control.height);
+        blit(&mut canvas, width, height, &pixels, region, control.blend_op == BlendOp::Over);
+
+        let delay_den = if control.delay_den == 0 { 100.0 } else { control.delay_den as f32 };
+        frames.push((canvas.clone(), (control.delay_num as f32) / delay_den * 1000.0));
+
+        match control.dispose_op {
+            DisposeOp::Background => clear(&mut canvas, width, height, region),
+            DisposeOp::Previous => canvas = previous.unwrap(),
+            _ => {}
+        }
+    }
+
+    if frames.len() <= 1 {
+        return Ok(None);
+    }
+
+    Ok(Some((width, height, frames)))
+}
+
+fn to_rgba(pixels: &[u8], color_type: ColorType) -> Vec<u8> {
+    match color_type {
+        ColorType::Rgba => pixels.to_vec(),
+        ColorType::Rgb => pixels.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        ColorType::GrayscaleAlpha => pixels.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        // Indexed gets expanded by the decoder, so it shouldn't show up here.
+        _ => pixels.iter().flat_map(|p| [*p, *p, *p, 255]).collect()
+    }
+}
+
+/// Draws a frame's pixels onto the canvas, either replacing what's there or alpha blending over it.
+fn blit(canvas: &mut [u8], width: u32, height: u32, pixels: &[u8], region: (u32, u32, u32, u32), blend: bool) {
+    let (left, top, frame_width, frame_height) = region;
+
+    for y in 0..frame_height {
+        for x in 0..frame_width {
+            if left + x >= width || top + y >= height {
+                continue;
+            }
+
+            let src = ((y * frame_width + x) * 4) as usize;
+            let dst = (((top + y) * width + left + x) * 4) as usize;
+
+            if src + 4 > pixels.len() {
+                return;
+            }
+
+            let alpha = pixels[src + 3] as f32 / 255.0;
+
+            if !blend || alpha >= 1.0 {
+                canvas[dst..(dst + 4)].copy_from_slice(&pixels[src..(src + 4)]);
+                continue;
+            }
+
+            if alpha <= 0.0 {
+                continue;
+            }
+
+            let dst_alpha = canvas[dst + 3] as f32 / 255.0;
+            let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+
+            for c in 0..3 {
+                let color = (pixels[src + c] as f32 * alpha + canvas[dst + c] as f32 * dst_alpha * (1.0 - alpha)) / out_alpha;
+                canvas[dst + c] = color.round() as u8;
+            }
+
+            canvas[dst + 3] = (out_alpha * 255.0).round() as u8;
+        }
+    }
+}
+
+fn clear(canvas: &mut [u8], width: u32, height: u32, region: (u32, u32, u32, u32)) {
+    let (left, top, frame_width, frame_height) = region;
+
+    for y in top..(top + frame_height).min(height) {
+        for x in left..(left + frame_width).min(width) {
+            let dst = ((y * width + x) * 4) as usize;
+            canvas[dst..(dst + 4)].fill(0);
+        }
+    }
+}
+
+/// Advances the current timing's animation, and only requests an update when the frame
+/// actually changes, so the renderer can keep sleeping between frames.
+pub fn tick_animation(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let timing = match data.current_timing {
+        Some(timing) => timing,
+        None => return
+    };
+
+    let (animation, looping) = unsafe {
+        match &(*timing).image().animation {
+            Some(animation) => (animation, (*timing).animation_settings.looping),
+            None => return
+        }
+    };
+
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+
+    if advance_frames(animation, looping, &mut data.animation_frame, &mut data.animation_time, millis) {
+        data.requires_update = true;
+    }
+}
+
+/// Moves an animation along by the elapsed time, returning true if the frame changed.
+pub fn advance_frames(animation: &Animation, looping: bool, frame: &mut usize, time: &mut f32, millis: f32) -> bool {
+    let total_frames = animation.frames.len();
+    if total_frames <= 1 {
+        return false;
+    }
+
+    *time += millis;
+
+    let mut changed = false;
+    loop {
+        let delay = animation.frames[(*frame).min(total_frames - 1)].delay.max(1.0);
+
+        if *time < delay {
+            break;
+        }
+
+        if *frame + 1 >= total_frames {
+            if !looping {
+                // Played once, hold on the last frame until something else resets it.
+                *time = 0.0;
+                break;
+            }
+
+            *frame = 0;
+        } else {
+            *frame += 1;
+        }
+
+        *time -= delay;
+        changed = true;
+    }
+
+    changed
+}
@@ -4,14 +4,25 @@ use std::thread::JoinHandle;
 use std::time::Duration;
 use cpal::{Device, Stream};
 use cpal::traits::{DeviceTrait, StreamTrait};
-//use pitch_detection::detector::mcleod::McLeodDetector;
-//use pitch_detection::detector::PitchDetector;
 use crate::SharedData;
 
+// How many samples the pitch is worked out from at once
+const PITCH_WINDOW: usize = 2048;
+// Lowest and highest pitch that counts, in Hz
+const MIN_PITCH: f32 = 70.0;
+const MAX_PITCH: f32 = 1000.0;
+// How periodic the sound has to be before it counts as having a pitch at all, from 0 to 1
+const PITCH_CLARITY: f32 = 0.6;
+// Peaks this close to the best one win if they come first, so the pitch doesn't jump down an octave
+const PITCH_PEAK_RATIO: f32 = 0.9;
+
 pub struct SharedAudioData {
     pub(crate) current_level: f32,
     pub(crate) current_pitch: f32,
     pub(crate) should_exit: bool,
+    // Level of whatever's being played back that the avatar should talk along to (TTS, etc.)
+    pub(crate) output_level: f32,
+    pub(crate) input_buffer_len: usize,
 }
 
 fn mul_to_db(mul: f32) -> f32 {
@@ -22,32 +33,104 @@ fn mul_to_db(mul: f32) -> f32 {
     };
 }
 
+/// Gets the level of a buffer in dB. The mic's level is taken from the energy of its whole buffer,
+/// so other buffers get scaled to the mic's buffer size to stay comparable with it.
+pub fn buffer_level(samples: &[f32], reference_len: usize) -> f32 {
+    if samples.is_empty() {
+        return -f32::INFINITY;
+    }
+
+    let mut sum = 0.0f32;
+    for x in samples {
+        sum += *x * *x;
+    }
+
+    if reference_len > 0 {
+        sum *= (reference_len as f32) / (samples.len() as f32);
+    }
+
+    mul_to_db(sum.sqrt())
+}
+
+/// Finds the pitch of a mono buffer in Hz, or 0 if it doesn't have a clear one.
+/// Uses the normalized square difference function from McLeod's method.
+fn detect_pitch(samples: &[f32], sample_rate: u32) -> f32 {
+    let min_lag = ((sample_rate as f32) / MAX_PITCH) as usize;
+    let max_lag = (((sample_rate as f32) / MIN_PITCH) as usize).min(samples.len() / 2);
+
+    if min_lag < 2 || min_lag >= max_lag {
+        return 0.0;
+    }
+
+    // One past either end, so every lag in range has neighbours to interpolate with.
+    let nsdf: Vec<f32> = ((min_lag - 1)..=(max_lag + 1)).map(|lag| {
+        let mut correlation = 0.0f32;
+        let mut energy = 0.0f32;
+
+        for i in 0..(samples.len() - lag) {
+            correlation += samples[i] * samples[i + lag];
+            energy += samples[i] * samples[i] + samples[i + lag] * samples[i + lag];
+        }
+
+        if energy > 0.0 {
+            2.0 * correlation / energy
+        } else {
+            0.0
+        }
+    }).collect();
+
+    let peaks: Vec<usize> = (1..(nsdf.len() - 1))
+        .filter(|&i| nsdf[i] > 0.0 && nsdf[i] >= nsdf[i - 1] && nsdf[i] > nsdf[i + 1])
+        .collect();
+
+    let best = peaks.iter().map(|&i| nsdf[i]).fold(0.0f32, f32::max);
+    if best < PITCH_CLARITY {
+        return 0.0;
+    }
+
+    let peak = match peaks.iter().find(|&&i| nsdf[i] >= best * PITCH_PEAK_RATIO) {
+        Some(&peak) => peak,
+        None => return 0.0
+    };
+
+    // Fits a parabola through the peak and its neighbours, for a pitch between whole lags.
+    let (left, center, right) = (nsdf[peak - 1], nsdf[peak], nsdf[peak + 1]);
+    let curvature = left - 2.0 * center + right;
+    let shift = if curvature != 0.0 {
+        0.5 * (left - right) / curvature
+    } else {
+        0.0
+    };
+
+    let lag = (peak + min_lag - 1) as f32 + shift;
+    (sample_rate as f32) / lag
+}
+
 pub fn spawn_audio_handler(data: &mut SharedData) -> Stream {
     let audio_data = unsafe { &mut *data.audio_data };
     let fuck_off = std::mem::take(&mut data.input_device);
     let device = fuck_off.unwrap();
 
-    //let mut pitch_detector: Arc<Mutex<McLeodDetector<f32>>> = Arc::new(Mutex::new(McLeodDetector::new(1024, 512)));
-
     let config = device.default_input_config().unwrap().config();
+    let channels = (config.channels as usize).max(1);
+    let sample_rate = config.sample_rate.0;
+
+    // The mic's samples, mixed down to mono, until there's enough to find the pitch of
+    let mut pitch_buffer: Vec<f32> = Vec::with_capacity(PITCH_WINDOW);
 
     let stream = device.build_input_stream(&config,
         move | d: &[f32], info: &cpal::InputCallbackInfo | {
-            let mut sum = 0.0f32;
-            for x in d {
-                sum += *x * *x;
-            }
+            audio_data.input_buffer_len = d.len();
+            audio_data.current_level = buffer_level(d, d.len());
 
-            audio_data.current_level = mul_to_db(sum.sqrt());
+            for frame in d.chunks(channels) {
+                pitch_buffer.push(frame.iter().sum::<f32>() / (frame.len() as f32));
 
-            /*let mut detector = pitch_detector.lock().unwrap();
-            let pitch = detector.get_pitch(d, config.sample_rate.0 as usize, 0.0, 0.4);
-
-            if pitch.is_some() {
-                audio_data.current_pitch = pitch.unwrap().frequency;
-            } else {
-                audio_data.current_pitch = 0.0;
-            }*/
+                if pitch_buffer.len() >= PITCH_WINDOW {
+                    audio_data.current_pitch = detect_pitch(&pitch_buffer, sample_rate);
+                    pitch_buffer.clear();
+                }
+            }
 
             ()
         },
@@ -60,4 +143,67 @@ pub fn spawn_audio_handler(data: &mut SharedData) -> Stream {
     stream.play().unwrap();
 
     stream
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use super::{detect_pitch, PITCH_WINDOW};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn tone(frequency: f32, harmonics: &[(f32, f32)]) -> Vec<f32> {
+        (0..PITCH_WINDOW).map(|i| {
+            let time = (i as f32) / (SAMPLE_RATE as f32);
+            let fundamental = (2.0 * PI * frequency * time).sin() * 0.5;
+
+            harmonics.iter().fold(fundamental, |sample, (multiple, amplitude)| {
+                sample + (2.0 * PI * frequency * multiple * time).sin() * amplitude
+            })
+        }).collect()
+    }
+
+    #[test]
+    fn detects_sine_waves() {
+        for frequency in [110.0, 220.0, 440.0] {
+            let pitch = detect_pitch(&tone(frequency, &[]), SAMPLE_RATE);
+            assert!((pitch - frequency).abs() < 2.0, "{} Hz was detected as {} Hz", frequency, pitch);
+        }
+    }
+
+    #[test]
+    fn stays_on_the_fundamental_with_strong_harmonics() {
+        // Every multiple of the period peaks too, picking a later one would land an octave or more too low.
+        for frequency in [110.0, 220.0] {
+            let pitch = detect_pitch(&tone(frequency, &[(2.0, 0.6), (3.0, 0.3)]), SAMPLE_RATE);
+            assert!((pitch - frequency).abs() < 2.0, "{} Hz was detected as {} Hz", frequency, pitch);
+        }
+    }
+
+    #[test]
+    fn ignores_a_weak_subharmonic() {
+        // The peak at twice the period comes out a little higher here, PITCH_PEAK_RATIO keeps it from winning.
+        for frequency in [220.0, 440.0] {
+            let pitch = detect_pitch(&tone(frequency, &[(0.5, 0.05)]), SAMPLE_RATE);
+            assert!((pitch - frequency).abs() < 2.0, "{} Hz was detected as {} Hz", frequency, pitch);
+        }
+    }
+
+    #[test]
+    fn silence_has_no_pitch() {
+        assert_eq!(detect_pitch(&vec![0.0; PITCH_WINDOW], SAMPLE_RATE), 0.0);
+    }
+
+    #[test]
+    fn noise_has_no_pitch() {
+        let mut seed = 0x2545f491u32;
+        let noise: Vec<f32> = (0..PITCH_WINDOW).map(|_| {
+            // xorshift, so the test is the same every run
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            (seed as f32) / (u32::MAX as f32) - 0.5
+        }).collect();
+
+        assert_eq!(detect_pitch(&noise, SAMPLE_RATE), 0.0);
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use cpal::{Device, FromSample, Host, SampleFormat, SizedSample, Stream, StreamConfig};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use hound::WavReader;
+use lewton::inside_ogg::OggStreamReader;
+use crate::SharedData;
+use crate::audio_handler::{buffer_level, SharedAudioData};
+
+/// A sound being played, read from its own sample rate at whatever rate the device runs at.
+pub struct Voice {
+    samples: Arc<Vec<f32>>,
+    sample_rate: u32,
+    position: f64,
+    volume: f32,
+    // Counts towards the level the avatar reacts to, so it talks along with it
+    affects_level: bool,
+    // Spoken by TTS, rather than a sound effect
+    is_speech: bool,
+}
+
+impl Voice {
+    fn sample(&self) -> f32 {
+        let index = self.position as usize;
+        let next = (index + 1).min(self.samples.len() - 1);
+        let blend = (self.position - (index as f64)) as f32;
+
+        self.samples[index] * (1.0 - blend) + self.samples[next] * blend
+    }
+
+    fn is_finished(&self) -> bool {
+        (self.position as usize) >= self.samples.len()
+    }
+}
+
+/// Mixes every playing voice into a single output stream.
+pub struct AudioOutput {
+    // The name it was opened with, which stays the same when it had to fall back to the default device
+    pub(crate) requested_name: String,
+    voices: Arc<Mutex<Vec<Voice>>>,
+    audio_data: *mut SharedAudioData,
+    _stream: Stream,
+}
+
+impl AudioOutput {
+    /// Opens the output device with the given name, or the default one if it's empty or missing.
+    pub fn open(host: &Host, device_name: &str, audio_data: *mut SharedAudioData) -> Option<AudioOutput> {
+        let device = find_output_device(host, device_name).or_else(|| host.default_output_device())?;
+        let name = device.name().unwrap_or_default();
+
+        let supported = match device.default_output_config() {
+            Ok(supported) => supported,
+            Err(err) => {
+                eprintln!("Failed to get the config for output device {}: {}", name, err);
+                return None;
+            }
+        };
+
+        let voices = Arc::new(Mutex::new(Vec::new()));
+        let config = supported.config();
+
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, voices.clone(), audio_data),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, voices.clone(), audio_data),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, voices.clone(), audio_data),
+            format => {
+                eprintln!("Unsupported sample format for output device {}: {}", name, format);
+                return None;
+            }
+        };
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Failed to open output device {}: {}", name, err);
+                return None;
+            }
+        };
+
+        if let Err(err) = stream.play() {
+            eprintln!("Failed to start output device {}: {}", name, err);
+            return None;
+        }
+
+        Some(AudioOutput {
+            requested_name: String::from(device_name),
+            voices,
+            audio_data,
+            _stream: stream
+        })
+    }
+
+    /// Plays mono samples, mixed in with anything else that's already playing.
+    pub fn play(&self, samples: Arc<Vec<f32>>, sample_rate: u32, volume: f32, affects_level: bool) {
+        self.push_voice(samples, sample_rate, volume, affects_level, false);
+    }
+
+    /// Plays speech, which the avatar always talks along to, and which stop_speech can cut off.
+    pub fn play_speech(&self, samples: Arc<Vec<f32>>, sample_rate: u32, volume: f32) {
+        self.push_voice(samples, sample_rate, volume, true, true);
+    }
+
+    fn push_voice(&self, samples: Arc<Vec<f32>>, sample_rate: u32, volume: f32, affects_level: bool, is_speech: bool) {
+        if samples.is_empty() || sample_rate == 0 {
+            return;
+        }
+
+        self.voices.lock().unwrap().push(Voice {
+            samples,
+            sample_rate,
+            position: 0.0,
+            volume,
+            affects_level,
+            is_speech
+        });
+    }
+
+    /// Stops whatever's being spoken, sound effects keep playing.
+    pub fn stop_speech(&self) {
+        self.voices.lock().unwrap().retain(|voice| !voice.is_speech);
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        // Held while resetting the level, so a callback that's still running can't set it again after.
+        let mut voices = self.voices.lock().unwrap();
+        voices.clear();
+
+        // Otherwise the mouth stays open on whatever was playing when the device went away.
+        unsafe {
+            (*self.audio_data).output_level = -f32::INFINITY;
+        }
+    }
+}
+
+fn find_output_device(host: &Host, device_name: &str) -> Option<Device> {
+    if device_name.is_empty() {
+        return None;
+    }
+
+    host.output_devices().ok()?.find(|device| device.name().is_ok_and(|name| name == device_name))
+}
+
+/// Opens the chosen output device if it isn't already, or if a different one was picked.
+pub fn ensure_audio_output(data: &mut SharedData) {
+    // Compared by what was asked for, a missing device would reopen the default one every time otherwise.
+    let is_open = data.audio_output.as_ref()
+        .is_some_and(|output| output.requested_name == data.output_device_name);
+
+    if !is_open {
+        data.audio_output = AudioOutput::open(&data.host, data.output_device_name.as_str(), data.audio_data);
+    }
+}
+
+pub fn output_device_names(host: &Host) -> Vec<String> {
+    match host.output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(_) => Vec::new()
+    }
+}
+
+fn build_stream<T>(device: &Device, config: &StreamConfig, voices: Arc<Mutex<Vec<Voice>>>, audio_data: *mut SharedAudioData) -> Result<Stream, cpal::BuildStreamError>
+    where T: SizedSample + FromSample<f32>
+{
+    let audio_data = unsafe { &mut *audio_data };
+    let channels = (config.channels as usize).max(1);
+    let output_rate = config.sample_rate.0 as f64;
+
+    device.build_output_stream(config,
+        move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut voices = voices.lock().unwrap();
+            let mut speech: Vec<f32> = Vec::with_capacity(output.len() / channels);
+
+            for frame in output.chunks_mut(channels) {
+                let mut mixed = 0.0f32;
+                let mut spoken = 0.0f32;
+
+                for voice in voices.iter_mut().filter(|voice| !voice.is_finished()) {
+                    let sample = voice.sample() * voice.volume;
+                    mixed += sample;
+
+                    if voice.affects_level {
+                        spoken += sample;
+                    }
+
+                    voice.position += (voice.sample_rate as f64) / output_rate;
+                }
+
+                for channel in frame.iter_mut() {
+                    *channel = T::from_sample(mixed.clamp(-1.0, 1.0));
+                }
+
+                speech.push(spoken);
+            }
+
+            let is_speaking = voices.iter().any(|voice| voice.affects_level && !voice.is_finished());
+            voices.retain(|voice| !voice.is_finished());
+
+            // Same analysis as the mic, so the timings' thresholds mean the same thing for both.
+            audio_data.output_level = if is_speaking {
+                buffer_level(&speech, audio_data.input_buffer_len)
+            } else {
+                -f32::INFINITY
+            };
+        },
+        move |err| {
+            eprintln!("{}", err);
+        },
+        None
+    )
+}
+
+/// Reads a WAV file into mono samples, averaging the channels together.
+pub fn read_wav(path: &str) -> Result<(Vec<f32>, u32), String> {
+    let mut reader = WavReader::open(path).map_err(|err| err.to_string())?;
+    let spec = reader.spec();
+    let channels = (spec.channels as usize).max(1);
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().map_while(Result::ok).collect(),
+        hound::SampleFormat::Int => {
+            let max = (1i64 << (spec.bits_per_sample.max(1) - 1)) as f32;
+            reader.samples::<i32>().map_while(Result::ok).map(|sample| (sample as f32) / max).collect()
+        }
+    };
+
+    let mono = interleaved.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / (frame.len() as f32))
+        .collect();
+
+    Ok((mono, spec.sample_rate))
+}
+
+/// Reads an Ogg Vorbis file into mono samples, averaging the channels together.
+pub fn read_ogg(path: &str) -> Result<(Vec<f32>, u32), String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mut reader = OggStreamReader::new(file).map_err(|err| err.to_string())?;
+    let channels = (reader.ident_hdr.audio_channels as usize).max(1);
+    let mut mono = Vec::new();
+
+    while let Some(packet) = reader.read_dec_packet_itl().map_err(|err| err.to_string())? {
+        mono.extend(packet.chunks(channels)
+            .map(|frame| frame.iter().map(|sample| (*sample as f32) / 32768.0).sum::<f32>() / (frame.len() as f32)));
+    }
+
+    Ok((mono, reader.ident_hdr.audio_sample_rate))
+}
+
+/// Reads a WAV or Ogg Vorbis file, going by its extension.
+pub fn read_sound(path: &str) -> Result<(Vec<f32>, u32), String> {
+    let extension = Path::new(path).extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "wav" => read_wav(path),
+        "ogg" => read_ogg(path),
+        _ => Err(format!("Unsupported sound format: {}", path))
+    }
+}
@@ -0,0 +1,74 @@
+use rand::Rng;
+use sdl2::render::Texture;
+use sdl2::surface::Surface;
+use serde::{Deserialize, Serialize};
+use crate::{current_expression, NANOS_TO_MILLIS, SharedData};
+
+pub struct BlinkLayer<'a> {
+    pub(crate) enabled: bool,
+    pub(crate) texture_path: String,
+    pub(crate) texture_surface: Surface<'a>,
+    pub(crate) texture: Texture,
+    // All of these are in milliseconds
+    pub(crate) duration: f32,
+    pub(crate) min_interval: f32,
+    pub(crate) max_interval: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SavedBlinkData {
+    pub(crate) enabled: bool,
+    pub(crate) texture_path: String,
+    pub(crate) duration: f32,
+    pub(crate) min_interval: f32,
+    pub(crate) max_interval: f32,
+}
+
+impl BlinkLayer<'_> {
+    pub fn save(&self) -> SavedBlinkData {
+        SavedBlinkData {
+            enabled: self.enabled,
+            texture_path: self.texture_path.clone(),
+            duration: self.duration,
+            min_interval: self.min_interval,
+            max_interval: self.max_interval
+        }
+    }
+
+    fn random_interval(&self) -> f32 {
+        if self.max_interval <= self.min_interval {
+            return self.min_interval;
+        }
+
+        rand::thread_rng().gen_range(self.min_interval..self.max_interval)
+    }
+}
+
+/// Counts down to the next blink, and only requests an update when a blink starts or ends,
+/// so the renderer can keep sleeping in between.
+pub fn tick_blink(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let blink = unsafe { &(*current_expression(data)).blink };
+
+    if !blink.enabled {
+        if data.is_blinking {
+            data.is_blinking = false;
+            data.requires_update = true;
+        }
+
+        return;
+    }
+
+    data.blink_timer -= ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+
+    if data.blink_timer > 0.0 {
+        return;
+    }
+
+    data.is_blinking = !data.is_blinking;
+    data.blink_timer = if data.is_blinking {
+        blink.duration
+    } else {
+        blink.random_interval()
+    };
+    data.requires_update = true;
+}
@@ -0,0 +1,111 @@
+use std::f32::consts::PI;
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BounceCurve {
+    Quadratic,
+    Sine,
+    EaseOutBack,
+    Elastic,
+}
+
+impl BounceCurve {
+    pub const ALL: [BounceCurve; 4] = [BounceCurve::Quadratic, BounceCurve::Sine, BounceCurve::EaseOutBack, BounceCurve::Elastic];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BounceCurve::Quadratic => "Quadratic",
+            BounceCurve::Sine => "Sine",
+            BounceCurve::EaseOutBack => "Ease Out Back",
+            BounceCurve::Elastic => "Elastic",
+        }
+    }
+
+    /// Gets the offset at a point through the bounce, with progress going from 0 to 1.
+    /// Every curve ends at 0, so the avatar always settles back where it started.
+    pub fn evaluate(&self, progress: f32) -> f32 {
+        let t = progress.clamp(0.0, 1.0);
+
+        match self {
+            BounceCurve::Quadratic => 4.0 * t * (1.0 - t),
+            BounceCurve::Sine => (PI * t).sin(),
+            // Kicks out straight away, then overshoots a little on the way back.
+            BounceCurve::EaseOutBack => {
+                let c1 = 1.70158;
+                let c3 = c1 + 1.0;
+                let eased = 1.0 + c3 * (t - 1.0).powi(3) + c1 * (t - 1.0).powi(2);
+
+                1.0 - eased
+            }
+            BounceCurve::Elastic => {
+                if t == 0.0 || t == 1.0 {
+                    return if t == 0.0 { 1.0 } else { 0.0 };
+                }
+
+                let eased = 2.0f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0;
+
+                1.0 - eased
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BounceSettings {
+    // in pixels, positive moves the avatar down
+    pub(crate) height: f32,
+    // in milliseconds
+    pub(crate) duration: f32,
+    pub(crate) curve: BounceCurve,
+}
+
+impl Default for BounceSettings {
+    fn default() -> Self {
+        BounceSettings {
+            height: 72.0,
+            duration: 250.0,
+            curve: BounceCurve::Quadratic
+        }
+    }
+}
+
+impl BounceSettings {
+    /// Converts the old frame based settings, which peaked at max_velocity^2 / 2 and
+    /// moved by the frame rate every frame, assuming the usual 90 Hz loop.
+    pub fn from_legacy(max_velocity: f32, total_velocity_frames: i32) -> BounceSettings {
+        BounceSettings {
+            height: max_velocity * max_velocity / 2.0,
+            duration: (total_velocity_frames as f32) * 1000.0 / (90.0 * 90.0),
+            curve: BounceCurve::Quadratic
+        }
+    }
+
+    pub fn offset(&self, elapsed: f32) -> f64 {
+        if self.duration <= 0.0 {
+            return 0.0;
+        }
+
+        (self.height * self.curve.evaluate(elapsed / self.duration)) as f64
+    }
+}
+
+/// Advances the bounce by the elapsed time, rather than by frames, so its speed
+/// doesn't depend on how fast the render loop happens to be running.
+pub fn tick_bounce(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let timing = match data.current_timing {
+        Some(timing) => timing,
+        None => return
+    };
+
+    let (should_bounce, bounce) = unsafe { ((*timing).should_bounce, &(*timing).bounce) };
+
+    if data.time_active <= 0.0 || !should_bounce || data.bounce_time >= bounce.duration {
+        return;
+    }
+
+    data.bounce_time = (data.bounce_time + ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32).min(bounce.duration);
+    data.current_velocity = bounce.offset(data.bounce_time);
+    data.requires_update = true;
+}
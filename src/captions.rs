@@ -0,0 +1,285 @@
+use std::fs;
+use std::time::SystemTime;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture};
+use sdl2::ttf::Font;
+use sdl2_sys::SDL_SetTextureAlphaMod;
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData};
+
+// How often the watched text file gets checked for changes, in milliseconds
+const WATCH_INTERVAL: f32 = 500.0;
+
+/// Speech bubbles and captions, drawn next to the avatar.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CaptionSettings {
+    pub(crate) enabled: bool,
+    // Only read on startup, since the font has to be loaded again to change it
+    pub(crate) font_size: u16,
+    pub(crate) text_color: [f32; 3],
+    pub(crate) bubble_color: [f32; 4],
+    // in pixels
+    pub(crate) padding: u32,
+    pub(crate) max_width: u32,
+    // Bottom middle of the bubble, in fractions of the window
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    // in milliseconds
+    pub(crate) fade_in: f32,
+    pub(crate) fade_out: f32,
+    // How long a caption stays up after fading in, in milliseconds. 0 keeps it up until it's replaced.
+    pub(crate) duration: f32,
+    // Shows whatever is in this file whenever it changes, empty to not watch anything
+    pub(crate) watch_path: String,
+}
+
+impl Default for CaptionSettings {
+    fn default() -> Self {
+        CaptionSettings {
+            enabled: false,
+            font_size: 20,
+            text_color: [0.0, 0.0, 0.0],
+            bubble_color: [1.0, 1.0, 1.0, 0.9],
+            padding: 10,
+            max_width: 320,
+            x: 0.5,
+            y: 0.25,
+            fade_in: 150.0,
+            fade_out: 300.0,
+            duration: 5000.0,
+            watch_path: String::new()
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum CaptionPhase {
+    Hidden,
+    FadingIn,
+    Showing,
+    FadingOut,
+}
+
+pub struct CaptionState {
+    pub(crate) text: String,
+    phase: CaptionPhase,
+    phase_time: f32,
+    // The wrapped text, rendered once whenever it changes
+    texture: Option<Texture>,
+    pub(crate) is_dirty: bool,
+    watch_timer: f32,
+    watch_modified: Option<SystemTime>,
+}
+
+impl CaptionState {
+    pub fn new() -> CaptionState {
+        CaptionState {
+            text: String::new(),
+            phase: CaptionPhase::Hidden,
+            phase_time: 0.0,
+            texture: None,
+            is_dirty: false,
+            watch_timer: 0.0,
+            watch_modified: None
+        }
+    }
+
+    fn alpha(&self, settings: &CaptionSettings) -> f32 {
+        match self.phase {
+            CaptionPhase::Hidden => 0.0,
+            CaptionPhase::FadingIn if settings.fade_in > 0.0 => (self.phase_time / settings.fade_in).min(1.0),
+            CaptionPhase::FadingOut if settings.fade_out > 0.0 => (1.0 - self.phase_time / settings.fade_out).max(0.0),
+            CaptionPhase::FadingOut => 0.0,
+            _ => 1.0
+        }
+    }
+}
+
+/// Shows a new caption, replacing whatever was up before. Empty text hides it.
+pub fn show_caption(data: &mut SharedData, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        hide_caption(data);
+        return;
+    }
+
+    let settings = &data.caption;
+    let state = &mut data.caption_state;
+
+    // Picks up from however visible it already was, so replacing a caption doesn't flash.
+    let alpha = state.alpha(settings);
+    state.text = String::from(text);
+    state.is_dirty = true;
+    state.phase = CaptionPhase::FadingIn;
+    state.phase_time = alpha * settings.fade_in;
+
+    data.requires_update = true;
+}
+
+pub fn hide_caption(data: &mut SharedData) {
+    let settings = &data.caption;
+    let state = &mut data.caption_state;
+
+    if state.phase == CaptionPhase::Hidden || state.phase == CaptionPhase::FadingOut {
+        return;
+    }
+
+    let alpha = state.alpha(settings);
+    state.phase = CaptionPhase::FadingOut;
+    state.phase_time = (1.0 - alpha) * settings.fade_out;
+
+    data.requires_update = true;
+}
+
+fn poll_watched_file(data: &mut SharedData, millis: f32) {
+    if data.caption.watch_path.is_empty() {
+        return;
+    }
+
+    data.caption_state.watch_timer += millis;
+    if data.caption_state.watch_timer < WATCH_INTERVAL {
+        return;
+    }
+
+    data.caption_state.watch_timer = 0.0;
+
+    let modified = match fs::metadata(data.caption.watch_path.as_str()).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return
+    };
+
+    if data.caption_state.watch_modified == Some(modified) {
+        return;
+    }
+
+    // Skip whatever was in there on startup, only new text gets shown.
+    let is_first_check = data.caption_state.watch_modified.is_none();
+    data.caption_state.watch_modified = Some(modified);
+
+    if is_first_check {
+        return;
+    }
+
+    match fs::read_to_string(data.caption.watch_path.as_str()) {
+        Ok(text) => show_caption(data, text.as_str()),
+        Err(err) => eprintln!("Failed to read caption file {}: {}", data.caption.watch_path, err)
+    }
+}
+
+/// Only wakes the renderer while fading, a caption that's just sitting there doesn't need redrawing.
+pub fn tick_captions(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+
+    if !data.caption.enabled {
+        if data.caption_state.phase != CaptionPhase::Hidden {
+            data.caption_state.phase = CaptionPhase::Hidden;
+            data.requires_update = true;
+        }
+
+        return;
+    }
+
+    poll_watched_file(data, millis);
+
+    let settings = &data.caption;
+    let state = &mut data.caption_state;
+
+    match state.phase {
+        CaptionPhase::Hidden => {}
+        CaptionPhase::FadingIn => {
+            state.phase_time += millis;
+
+            if state.phase_time >= settings.fade_in {
+                state.phase = CaptionPhase::Showing;
+                state.phase_time = 0.0;
+            }
+
+            data.requires_update = true;
+        }
+        CaptionPhase::Showing => {
+            state.phase_time += millis;
+
+            if settings.duration > 0.0 && state.phase_time >= settings.duration {
+                state.phase = CaptionPhase::FadingOut;
+                state.phase_time = 0.0;
+                data.requires_update = true;
+            }
+        }
+        CaptionPhase::FadingOut => {
+            state.phase_time += millis;
+
+            if state.phase_time >= settings.fade_out {
+                state.phase = CaptionPhase::Hidden;
+                state.phase_time = 0.0;
+            }
+
+            data.requires_update = true;
+        }
+    }
+}
+
+fn to_color(color: [f32; 3], alpha: f32) -> Color {
+    Color::RGBA((color[0] * 255.0) as u8, (color[1] * 255.0) as u8, (color[2] * 255.0) as u8, (alpha * 255.0) as u8)
+}
+
+unsafe fn rebuild_texture(data: &mut SharedData, font: &Font) {
+    let state = &mut data.caption_state;
+    state.is_dirty = false;
+
+    if let Some(texture) = state.texture.take() {
+        texture.destroy();
+    }
+
+    // Wrapped by SDL_ttf, on word boundaries where it can.
+    let surface = match font.render(state.text.as_str()).blended_wrapped(to_color(data.caption.text_color, 1.0), data.caption.max_width.max(16)) {
+        Ok(surface) => surface,
+        Err(err) => {
+            eprintln!("Failed to render caption: {}", err);
+            return;
+        }
+    };
+
+    state.texture = (*data.pngtuber_canvas).create_texture_from_surface(&surface).ok();
+    drop(surface.context());
+}
+
+pub unsafe fn render_captions(window_size: (u32, u32), data: &mut SharedData, font: &Font) {
+    let alpha = data.caption_state.alpha(&data.caption);
+    if !data.caption.enabled || alpha <= 0.0 {
+        return;
+    }
+
+    if data.caption_state.is_dirty {
+        rebuild_texture(data, font);
+    }
+
+    let texture = match &data.caption_state.texture {
+        Some(texture) => texture,
+        None => return
+    };
+
+    let settings = &data.caption;
+    let canvas = data.pngtuber_canvas;
+    let query = texture.query();
+
+    let width = query.width + settings.padding * 2;
+    let height = query.height + settings.padding * 2;
+
+    // Kept inside the window, no matter where it's anchored.
+    let x = ((window_size.0 as f32) * settings.x - (width as f32) / 2.0) as i32;
+    let y = ((window_size.1 as f32) * settings.y) as i32 - (height as i32);
+    let x = x.clamp(0, ((window_size.0 as i32) - (width as i32)).max(0));
+    let y = y.clamp(0, ((window_size.1 as i32) - (height as i32)).max(0));
+
+    let bubble = &settings.bubble_color;
+    (*canvas).set_blend_mode(BlendMode::Blend);
+    (*canvas).set_draw_color(to_color([bubble[0], bubble[1], bubble[2]], bubble[3] * alpha));
+    (*canvas).fill_rect(Rect::new(x, y, width, height)).unwrap();
+    (*canvas).set_blend_mode(BlendMode::None);
+
+    SDL_SetTextureAlphaMod(texture.raw(), (alpha * 255.0) as u8);
+    (*canvas).copy(texture, None, Option::from(Rect::new(x + settings.padding as i32, y + settings.padding as i32, query.width, query.height))).unwrap();
+    SDL_SetTextureAlphaMod(texture.raw(), 255);
+}
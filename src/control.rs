@@ -0,0 +1,93 @@
+use std::io::{BufRead, BufReader};
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use crate::{switch_expression, SharedData};
+use crate::actions::{find_action, start_action};
+use crate::captions::{hide_caption, show_caption};
+use crate::lipsync::{speak_text, stop_speaking};
+
+/// Listens on localhost for line based commands, so other programs (stream decks, chat bots, etc.)
+/// can drive the avatar. Commands are handed to the render loop through the returned receiver.
+pub fn spawn_control_server(port: u16) -> Option<Receiver<String>> {
+    if port == 0 {
+        return None;
+    }
+
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Failed to start the control interface on port {}: {}", port, err);
+            return None;
+        }
+    };
+
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    let line = line.trim();
+
+                    if !line.is_empty() && sender.send(String::from(line)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    Some(receiver)
+}
+
+fn handle_command(data: &mut SharedData, line: &str) {
+    let (command, argument) = match line.split_once(' ') {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (line, "")
+    };
+
+    match command.to_lowercase().as_str() {
+        "action" => match find_action(data, argument) {
+            Some(index) => start_action(data, index),
+            None => eprintln!("Unknown action: {}", argument)
+        },
+
+        "expression" => {
+            let target = unsafe { (*data.expressions).iter() }
+                .position(|expression| expression.name.eq_ignore_ascii_case(argument));
+
+            match target {
+                Some(index) => switch_expression(data, index),
+                None => eprintln!("Unknown expression: {}", argument)
+            }
+        }
+
+        "say" => show_caption(data, argument),
+
+        "clear" => hide_caption(data),
+
+        "speak" => speak_text(data, argument),
+
+        "stop" => stop_speaking(data),
+
+        _ => eprintln!("Unknown control command: {}", line)
+    }
+}
+
+/// Runs every command that came in since the last frame.
+pub fn poll_control(data: &mut SharedData) {
+    let mut commands = Vec::new();
+
+    if let Some(receiver) = &data.control {
+        while let Ok(command) = receiver.try_recv() {
+            commands.push(command);
+        }
+    }
+
+    for command in commands {
+        handle_command(data, command.as_str());
+    }
+}
@@ -0,0 +1,98 @@
+use std::os::raw::c_int;
+use sdl2_sys::SDL_GetGlobalMouseState;
+use serde::{Deserialize, Serialize};
+use crate::{current_expression, NANOS_TO_MILLIS, SharedData};
+
+/// Moves a layer (usually the pupils) towards the cursor, anywhere on the desktop.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CursorTracking {
+    pub(crate) enabled: bool,
+    // How far the layer can move from where it normally sits, in pixels
+    pub(crate) radius: f32,
+    // How far away the cursor has to be for the layer to move the full radius, in pixels
+    pub(crate) reach: f32,
+    // Where the eyes are, in fractions of the window
+    pub(crate) anchor_x: f32,
+    pub(crate) anchor_y: f32,
+    // in milliseconds, 0 follows the cursor straight away
+    pub(crate) smoothing: f32,
+}
+
+impl Default for CursorTracking {
+    fn default() -> Self {
+        CursorTracking {
+            enabled: false,
+            radius: 6.0,
+            reach: 400.0,
+            anchor_x: 0.5,
+            anchor_y: 0.4,
+            smoothing: 60.0
+        }
+    }
+}
+
+impl CursorTracking {
+    fn target(&self, window_size: (u32, u32), cursor: (i32, i32)) -> (f64, f64) {
+        let dx = (cursor.0 as f64) - (window_size.0 as f64) * (self.anchor_x as f64);
+        let dy = (cursor.1 as f64) - (window_size.1 as f64) * (self.anchor_y as f64);
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        if distance < 0.001 {
+            return (0.0, 0.0);
+        }
+
+        let amount = (self.radius as f64) * (distance / (self.reach as f64).max(1.0)).min(1.0);
+        (dx / distance * amount, dy / distance * amount)
+    }
+}
+
+/// Gets the cursor's position relative to the window, even when it's outside of it.
+pub fn global_cursor_position(window_position: (i32, i32)) -> (i32, i32) {
+    let mut x: c_int = 0;
+    let mut y: c_int = 0;
+
+    unsafe {
+        SDL_GetGlobalMouseState(&mut x, &mut y);
+    }
+
+    (x - window_position.0, y - window_position.1)
+}
+
+pub fn tick_eye_tracking(data: &mut SharedData, window_size: (u32, u32), nanos_since_last_frame: u128) {
+    let millis = (nanos_since_last_frame as f64) / NANOS_TO_MILLIS;
+    let cursor = data.cursor_position;
+    let mut changed = false;
+
+    for layer in unsafe { (*current_expression(data)).layers.iter_mut() } {
+        let previous = layer.track_offset;
+
+        let target = if layer.tracking.enabled {
+            layer.tracking.target(window_size, cursor)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let blend = if layer.tracking.smoothing <= 0.0 {
+            1.0
+        } else {
+            (millis / (layer.tracking.smoothing as f64)).min(1.0)
+        };
+
+        layer.track_offset.0 += (target.0 - layer.track_offset.0) * blend;
+        layer.track_offset.1 += (target.1 - layer.track_offset.1) * blend;
+
+        // Snap once it's close enough, so the renderer can sleep.
+        if (target.0 - layer.track_offset.0).abs() < 0.05 && (target.1 - layer.track_offset.1).abs() < 0.05 {
+            layer.track_offset = target;
+        }
+
+        if layer.track_offset != previous {
+            changed = true;
+        }
+    }
+
+    if changed {
+        data.requires_update = true;
+    }
+}
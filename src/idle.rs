@@ -0,0 +1,122 @@
+use std::f64::consts::PI;
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData};
+
+// How long it takes the idle motion to ease in or out, in milliseconds
+const IDLE_BLEND_TIME: f32 = 300.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct IdleSettings {
+    pub(crate) enabled: bool,
+    // Fraction of the avatar's height
+    pub(crate) breathing_amount: f32,
+    // in milliseconds
+    pub(crate) breathing_period: f32,
+    // in pixels
+    pub(crate) sway_amount: f32,
+    // in milliseconds
+    pub(crate) sway_period: f32,
+    // Idle motion is slow, so it doesn't need anywhere near the full frame rate.
+    pub(crate) fps: f32,
+}
+
+impl Default for IdleSettings {
+    fn default() -> Self {
+        IdleSettings {
+            enabled: false,
+            breathing_amount: 0.015,
+            breathing_period: 4000.0,
+            sway_amount: 4.0,
+            sway_period: 6000.0,
+            fps: 20.0
+        }
+    }
+}
+
+pub struct IdleState {
+    time: f32,
+    // Eases between 0 while speaking and 1 while silent, so the motion doesn't snap.
+    weight: f32,
+    frame_timer: f32,
+}
+
+impl IdleState {
+    pub fn new() -> IdleState {
+        IdleState {
+            time: 0.0,
+            weight: 0.0,
+            frame_timer: 0.0
+        }
+    }
+}
+
+/// Gets the breathing scale and horizontal sway offset for this frame.
+pub fn idle_motion(data: &SharedData) -> (f64, f64) {
+    let settings = &data.idle;
+    let state = &data.idle_state;
+
+    if state.weight <= 0.0 {
+        return (1.0, 0.0);
+    }
+
+    let time = state.time as f64;
+    let weight = state.weight as f64;
+
+    let breathing = if settings.breathing_period > 0.0 {
+        (settings.breathing_amount as f64) * (2.0 * PI * time / (settings.breathing_period as f64)).sin()
+    } else {
+        0.0
+    };
+
+    let sway = if settings.sway_period > 0.0 {
+        (settings.sway_amount as f64) * (2.0 * PI * time / (settings.sway_period as f64)).sin()
+    } else {
+        0.0
+    };
+
+    (1.0 + breathing * weight, sway * weight)
+}
+
+pub fn tick_idle(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+    let state = &mut data.idle_state;
+
+    if !data.idle.enabled {
+        if state.weight > 0.0 {
+            state.weight = 0.0;
+            data.requires_update = true;
+        }
+
+        return;
+    }
+
+    let previous_weight = state.weight;
+    let target = if data.is_speaking { 0.0 } else { 1.0 };
+    let blend = (millis / IDLE_BLEND_TIME).min(1.0);
+    state.weight += (target - state.weight) * blend;
+
+    if (target - state.weight).abs() < 0.01 {
+        state.weight = target;
+    }
+
+    // Fully eased out, nothing left to draw
+    if state.weight <= 0.0 {
+        state.time = 0.0;
+
+        if previous_weight > 0.0 {
+            data.requires_update = true;
+        }
+
+        return;
+    }
+
+    state.time += millis;
+    state.frame_timer += millis;
+
+    let frame_time = 1000.0 / data.idle.fps.max(1.0);
+    if state.frame_timer >= frame_time {
+        state.frame_timer %= frame_time;
+        data.requires_update = true;
+    }
+}
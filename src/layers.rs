@@ -0,0 +1,194 @@
+use sdl2::rect::Rect;
+use sdl2::render::Texture;
+use sdl2::surface::Surface;
+use sdl2_sys::SDL_DestroyTexture;
+use serde::{Deserialize, Serialize};
+use crate::{current_expression, current_timings, load_image, SharedData};
+use crate::eye_tracking::CursorTracking;
+use crate::mesh::{copy_warped, MeshSettings};
+use crate::parameters::parameter_modifiers;
+use crate::transform::{avatar_pivot, avatar_transform, AvatarTransform};
+
+pub struct LayerImage<'a> {
+    pub(crate) texture_path: String,
+    pub(crate) texture_surface: Surface<'a>,
+    pub(crate) texture: Texture,
+}
+
+/// A single part of the avatar (body, head, eyes, mouth, etc.), drawn in order on top of
+/// the current timing's texture.
+pub struct AvatarLayer<'a> {
+    pub(crate) name: String,
+    pub(crate) offset_x: i32,
+    pub(crate) offset_y: i32,
+    pub(crate) follows_bounce: bool,
+    pub(crate) image: LayerImage<'a>,
+    // Indexed the same as the expression's speech timings, None uses the base image.
+    pub(crate) timing_images: Vec<Option<LayerImage<'a>>>,
+    // Shared by the base image and every timing image
+    pub(crate) mesh: MeshSettings,
+    pub(crate) tracking: CursorTracking,
+    // Where the cursor tracking has moved the layer to
+    pub(crate) track_offset: (f64, f64),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SavedLayerData {
+    pub(crate) name: String,
+    pub(crate) offset_x: i32,
+    pub(crate) offset_y: i32,
+    pub(crate) follows_bounce: bool,
+    pub(crate) texture_path: String,
+    // Empty paths mean the timing uses the base image.
+    #[serde(default)]
+    pub(crate) timing_texture_paths: Vec<String>,
+    #[serde(default)]
+    pub(crate) mesh: MeshSettings,
+    #[serde(default)]
+    pub(crate) tracking: CursorTracking,
+}
+
+impl LayerImage<'_> {
+    pub fn load(data: &SharedData, texture_path: &str) -> LayerImage<'static> {
+        let (png_surface, png_texture) = load_image(data, texture_path);
+
+        LayerImage {
+            texture_path: String::from(texture_path),
+            texture_surface: png_surface,
+            texture: png_texture
+        }
+    }
+
+    pub unsafe fn destroy(&self) {
+        drop(self.texture_surface.context());
+        SDL_DestroyTexture(self.texture.raw());
+    }
+}
+
+impl AvatarLayer<'_> {
+    pub fn new(data: &SharedData) -> AvatarLayer<'static> {
+        AvatarLayer {
+            name: String::from("Layer"),
+            offset_x: 0,
+            offset_y: 0,
+            follows_bounce: true,
+            image: LayerImage::load(data, ""),
+            timing_images: Vec::new(),
+            mesh: MeshSettings::default(),
+            tracking: CursorTracking::default(),
+            track_offset: (0.0, 0.0)
+        }
+    }
+
+    pub fn load(data: &SharedData, layer: &SavedLayerData) -> AvatarLayer<'static> {
+        let mut timing_images = Vec::new();
+
+        for path in layer.timing_texture_paths.iter() {
+            if path.is_empty() {
+                timing_images.push(None);
+            } else {
+                timing_images.push(Some(LayerImage::load(data, path.as_str())));
+            }
+        }
+
+        AvatarLayer {
+            name: layer.name.clone(),
+            offset_x: layer.offset_x,
+            offset_y: layer.offset_y,
+            follows_bounce: layer.follows_bounce,
+            image: LayerImage::load(data, layer.texture_path.as_str()),
+            timing_images,
+            mesh: layer.mesh.clone(),
+            tracking: layer.tracking.clone(),
+            track_offset: (0.0, 0.0)
+        }
+    }
+
+    pub fn save(&self) -> SavedLayerData {
+        let mut timing_texture_paths = Vec::new();
+
+        for image in self.timing_images.iter() {
+            timing_texture_paths.push(match image {
+                Some(image) => image.texture_path.clone(),
+                None => String::new()
+            });
+        }
+
+        SavedLayerData {
+            name: self.name.clone(),
+            offset_x: self.offset_x,
+            offset_y: self.offset_y,
+            follows_bounce: self.follows_bounce,
+            texture_path: self.image.texture_path.clone(),
+            timing_texture_paths,
+            mesh: self.mesh.clone(),
+            tracking: self.tracking.clone()
+        }
+    }
+
+    pub fn image_for_timing(&self, timing_index: Option<usize>) -> &LayerImage<'_> {
+        if let Some(Some(image)) = timing_index.and_then(|i| self.timing_images.get(i)) {
+            return image;
+        }
+
+        &self.image
+    }
+
+    pub unsafe fn destroy(&self) {
+        self.image.destroy();
+
+        for image in self.timing_images.iter().flatten() {
+            image.destroy();
+        }
+    }
+}
+
+/// Gets the index of the current timing within the current expression, if it's in there.
+/// It might not be, while the previous expression's timing is still being released.
+pub fn current_timing_index(data: &SharedData) -> Option<usize> {
+    let current = data.current_timing?;
+
+    unsafe {
+        (*current_timings(data)).iter().position(|timing| std::ptr::eq(timing, current))
+    }
+}
+
+/// Layers are expected to share the same canvas size, so each one is scaled to the
+/// avatar's height on its own, and centered like the timing's texture is.
+pub unsafe fn render_layers(window_size: (u32, u32), window_height: u32, data: &SharedData) {
+    let canvas = data.pngtuber_canvas;
+    let timing_index = current_timing_index(data);
+    let pivot = avatar_pivot(window_size, data);
+
+    for layer in (*current_expression(data)).layers.iter() {
+        let image = layer.image_for_timing(timing_index);
+
+        let height_percent = (window_height as f64) / (image.texture_surface.height() as f64);
+        let new_width = ((image.texture_surface.width() as f64) * height_percent) as i32;
+
+        let x = ((window_size.0 as i32) / 2) - (new_width / 2) + layer.offset_x;
+        let y = ((window_size.1 - window_height) as i32) + layer.offset_y;
+        let dest = Rect::new(x, y, new_width as u32, window_height);
+
+        let mut transform = if layer.follows_bounce {
+            avatar_transform(data)
+        } else {
+            AvatarTransform::identity()
+        };
+
+        let modifiers = parameter_modifiers(data, layer.name.as_str());
+        transform.offset_x += modifiers.offset_x + layer.track_offset.0;
+        transform.offset_y += modifiers.offset_y + layer.track_offset.1;
+        transform.scale_x *= modifiers.scale;
+        transform.scale_y *= modifiers.scale;
+        transform.rotation += modifiers.rotation;
+
+        match modifiers.crossfade {
+            Some((amount, other)) => {
+                copy_warped(canvas, &image.texture, None, dest, &layer.mesh, data, &transform, pivot, (1.0 - amount) * modifiers.opacity, (255, 255, 255));
+                copy_warped(canvas, &(*other).texture, None, dest, &layer.mesh, data, &transform, pivot, amount * modifiers.opacity, (255, 255, 255));
+            }
+            None => copy_warped(canvas, &image.texture, None, dest, &layer.mesh, data, &transform, pivot, modifiers.opacity, (255, 255, 255))
+        }
+    }
+}
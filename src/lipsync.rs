@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData};
+use crate::captions::show_caption;
+use crate::tts::{start_tts, stop_tts};
+
+// Quiet enough to fall below every timing's threshold
+pub const SILENT_LEVEL: f32 = -100.0;
+
+/// Makes the avatar talk from typed text instead of a mic, by faking the level the
+/// timings get picked from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LipSyncSettings {
+    // Syllables per second
+    pub(crate) rate: f32,
+    // How much of each syllable the mouth is open for, from 0 to 1
+    pub(crate) open_fraction: f32,
+    // The level (in dB) for the widest vowels, narrower ones go quieter so they can use other timings
+    pub(crate) open_level: f32,
+    pub(crate) narrow_level: f32,
+    // Pauses, in syllables
+    pub(crate) word_gap: f32,
+    pub(crate) comma_pause: f32,
+    pub(crate) sentence_pause: f32,
+    // Also shows the text as a caption while it's spoken
+    pub(crate) show_caption: bool,
+}
+
+impl Default for LipSyncSettings {
+    fn default() -> Self {
+        LipSyncSettings {
+            rate: 5.0,
+            open_fraction: 0.6,
+            open_level: -5.0,
+            narrow_level: -20.0,
+            word_gap: 0.25,
+            comma_pause: 1.0,
+            sentence_pause: 2.0,
+            show_caption: true
+        }
+    }
+}
+
+pub struct LipSyncSegment {
+    // in milliseconds
+    duration: f32,
+    level: f32,
+}
+
+pub struct LipSyncState {
+    timeline: Vec<LipSyncSegment>,
+    segment: usize,
+    time: f32,
+}
+
+impl LipSyncState {
+    pub fn new() -> LipSyncState {
+        LipSyncState {
+            timeline: Vec::new(),
+            segment: 0,
+            time: 0.0
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.segment < self.timeline.len()
+    }
+
+    pub fn level(&self) -> f32 {
+        match self.timeline.get(self.segment) {
+            Some(segment) => segment.level,
+            None => SILENT_LEVEL
+        }
+    }
+}
+
+/// How wide the mouth opens for a vowel, from 0 to 1.
+fn vowel_openness(c: char) -> Option<f32> {
+    match c.to_ascii_lowercase() {
+        'a' | 'o' => Some(1.0),
+        'e' | 'i' => Some(0.6),
+        'u' | 'y' => Some(0.3),
+        _ => None
+    }
+}
+
+/// Splits text into syllables by its vowel groups, which is nowhere near real phonetics,
+/// but it's plenty for a mouth flapping along to it.
+pub fn build_timeline(text: &str, settings: &LipSyncSettings) -> Vec<LipSyncSegment> {
+    let syllable = 1000.0 / settings.rate.max(0.1);
+    let open_fraction = settings.open_fraction.clamp(0.05, 1.0);
+    let mut timeline = Vec::new();
+
+    for word in text.split_whitespace() {
+        let mut syllables: Vec<f32> = Vec::new();
+        let mut previous_was_vowel = false;
+
+        for c in word.chars() {
+            match vowel_openness(c) {
+                Some(openness) => {
+                    if previous_was_vowel {
+                        // Diphthongs are one syllable, opened as wide as their widest vowel.
+                        let last = syllables.len() - 1;
+                        syllables[last] = syllables[last].max(openness);
+                    } else {
+                        syllables.push(openness);
+                    }
+
+                    previous_was_vowel = true;
+                }
+                None => previous_was_vowel = false
+            }
+        }
+
+        // Words without vowels ("hmm", numbers) still move the mouth once.
+        if syllables.is_empty() && word.chars().any(|c| c.is_alphanumeric()) {
+            syllables.push(0.3);
+        }
+
+        for openness in syllables {
+            timeline.push(LipSyncSegment {
+                duration: syllable * open_fraction,
+                level: settings.narrow_level + (settings.open_level - settings.narrow_level) * openness
+            });
+
+            if open_fraction < 1.0 {
+                timeline.push(LipSyncSegment {
+                    duration: syllable * (1.0 - open_fraction),
+                    level: SILENT_LEVEL
+                });
+            }
+        }
+
+        let pause = match word.chars().last() {
+            Some('.') | Some('!') | Some('?') => settings.sentence_pause,
+            Some(',') | Some(';') | Some(':') => settings.comma_pause,
+            _ => settings.word_gap
+        };
+
+        if pause > 0.0 {
+            timeline.push(LipSyncSegment {
+                duration: syllable * pause,
+                level: SILENT_LEVEL
+            });
+        }
+    }
+
+    timeline
+}
+
+/// Starts speaking the given text, cutting off whatever was being said before.
+/// With TTS turned on, the avatar follows the voice's level instead of a made up timeline.
+pub fn speak_text(data: &mut SharedData, text: &str) {
+    stop_speaking(data);
+
+    if data.tts.enabled {
+        start_tts(data, text);
+    } else {
+        data.lipsync_state = LipSyncState {
+            timeline: build_timeline(text, &data.lipsync),
+            ..LipSyncState::new()
+        };
+    }
+
+    if data.lipsync.show_caption {
+        show_caption(data, text);
+    }
+
+    data.requires_update = true;
+}
+
+pub fn stop_speaking(data: &mut SharedData) {
+    data.lipsync_state = LipSyncState::new();
+    stop_tts(data);
+}
+
+pub fn tick_lipsync(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let state = &mut data.lipsync_state;
+
+    if !state.is_playing() {
+        if !state.timeline.is_empty() {
+            state.timeline.clear();
+        }
+
+        return;
+    }
+
+    state.time += ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+
+    while let Some(segment) = state.timeline.get(state.segment) {
+        if state.time < segment.duration {
+            break;
+        }
+
+        state.time -= segment.duration;
+        state.segment += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_timeline, LipSyncSettings, SILENT_LEVEL};
+
+    #[test]
+    fn splits_syllables_and_pauses() {
+        let timeline = build_timeline("Hi, hmm okay.", &LipSyncSettings::default());
+
+        // "Hi," is one syllable and a comma pause, "hmm" has no vowels but still gets one,
+        // and the "ay" in "okay." is a single syllable, followed by a sentence pause.
+        let expected = [
+            (120.0, -11.0), (80.0, SILENT_LEVEL), (200.0, SILENT_LEVEL),
+            (120.0, -15.5), (80.0, SILENT_LEVEL), (50.0, SILENT_LEVEL),
+            (120.0, -5.0), (80.0, SILENT_LEVEL), (120.0, -5.0), (80.0, SILENT_LEVEL), (400.0, SILENT_LEVEL)
+        ];
+
+        assert_eq!(timeline.len(), expected.len());
+
+        for (index, (segment, (duration, level))) in timeline.iter().zip(expected).enumerate() {
+            assert!((segment.duration - duration).abs() < 0.01, "segment {} lasts {} ms, not {} ms", index, segment.duration, duration);
+            assert!((segment.level - level).abs() < 0.01, "segment {} is at {} dB, not {} dB", index, segment.level, level);
+        }
+    }
+}
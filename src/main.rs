@@ -1,45 +1,93 @@
+use std::cell::Cell;
 use std::ffi::{c_char, c_void, CString};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::mem::size_of;
 use std::ops::Index;
 use std::ptr::null_mut;
+use std::sync::mpsc::Receiver;
 use std::thread::{JoinHandle, sleep};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use close_file::Closable;
 use cpal::{Device, Host, Stream};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use imgui::{Condition, Context, DrawCmd, TreeNodeFlags, Ui};
+use imgui::{Condition, Context, DrawCmd, ItemHoveredFlags, TreeNodeFlags, Ui};
 use imgui::internal::{RawCast, RawWrapper};
 use mint::{Vector2, Vector3};
 //use pitch_detection::detector::mcleod::McLeodDetector;
-use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
 use rfd::FileDialog;
 use sdl2::event::Event;
 use sdl2::EventPump;
 use sdl2::image::{InitFlag, LoadSurface, LoadTexture};
+use sdl2::keyboard::Keycode;
 use sdl2::libc::{c_int, free, malloc, size_t};
 use sdl2::mouse::MouseButton;
 use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
 use sdl2::render::{BlendMode, Canvas, Texture, TextureAccess, WindowCanvas};
 use sdl2::surface::Surface;
-use sdl2::sys::SDL_WindowFlags::SDL_WINDOW_SHOWN;
 use sdl2::ttf::Font;
 use sdl2::video::GLProfile;
 use sdl2_sys::{SDL_BlendFactor, SDL_BlendOperation, SDL_Color, SDL_ComposeCustomBlendMode, SDL_DestroyTexture, SDL_FPoint, SDL_RenderGeometry, SDL_SetRenderDrawBlendMode, SDL_Texture, SDL_Vertex};
 use serde::{Deserialize, Serialize};
 use serde::de::Error;
-use winsafe::{COLORREF, HWND};
-use winsafe::co::{GWLP, LWA, WS_EX};
-use winsafe::prelude::*;
+use crate::animation::{AnimationSettings, tick_animation};
 use crate::audio_handler::{SharedAudioData, spawn_audio_handler};
+use crate::blink::{BlinkLayer, SavedBlinkData, tick_blink};
+use crate::audio_output::{AudioOutput, output_device_names};
+use crate::bounce::{BounceCurve, BounceSettings, tick_bounce};
+use crate::idle::{IdleSettings, IdleState, tick_idle};
+use crate::layers::{AvatarLayer, LayerImage, render_layers, SavedLayerData};
+use crate::actions::{Action, ActionSettings, ActionState, render_action, start_action, tick_actions};
+use crate::captions::{CaptionSettings, CaptionState, hide_caption, render_captions, show_caption, tick_captions};
+use crate::control::{poll_control, spawn_control_server};
+use crate::eye_tracking::{global_cursor_position, tick_eye_tracking};
+use crate::lipsync::{LipSyncSettings, LipSyncState, speak_text, stop_speaking, tick_lipsync};
+use crate::mesh::{copy_warped, DeformKind, MeshDeformer, MeshSettings};
+use crate::parameters::{Parameter, ParameterInput, ParameterOutput, ParameterSettings, parameter_modifiers, tick_parameters};
+use crate::platform::{create_window, find_font, FrameShape, TransparencyMode, WindowTransparency};
+use crate::plugins::{load_plugins, PluginSettings, PluginState, tick_plugins};
+use crate::poke::{PokeSettings, PokeState, reload_poke_image, render_poke, start_poke, tick_poke};
+use crate::particles::{Emitter, EmitterSettings, Particle, render_particles, tick_particles};
+use crate::physics::{PhysicsState, SpringSettings, tick_physics};
+use crate::scripting::{reload_script, ScriptState, tick_scripts};
+use crate::sleep::{SleepSettings, SleepState, tick_sleep};
+use crate::sounds::{play_sound, play_sounds_for_expression, SoundEffect, SoundEffectSettings};
+use crate::transform::{avatar_pivot, avatar_transform, tick_wobble, WobbleSettings};
+use crate::tts::{tick_tts, TtsEngine, TtsSettings, TtsState};
+use crate::variants::{ImageVariant, pick_variant, TimingImage, VariantMode};
+use crate::transition::{finish_transition, tick_transition, transition_progress, TransitionEffect, TransitionSettings};
 
 use crate::imgui_support::SdlPlatform;
 
 mod imgui_support;
 mod audio_handler;
+mod audio_output;
+mod actions;
+mod animation;
+mod blink;
+mod bounce;
+mod captions;
+mod control;
+mod eye_tracking;
+mod idle;
+mod layers;
+mod lipsync;
+mod mesh;
+mod parameters;
+mod particles;
+mod physics;
+mod platform;
+mod plugins;
+mod poke;
+mod scripting;
+mod sleep;
+mod sounds;
+mod transform;
+mod transition;
+mod tts;
+mod variants;
 
 const SHOW_DEBUG: bool = false;
 const DEBUG_ALWAYS_UPDATE: bool = false;
@@ -47,9 +95,10 @@ const DEBUG_ALWAYS_UPDATE: bool = false;
 struct SharedData {
     last_frame: SystemTime,
     current_velocity: f64,
-    current_max_velocity: f64,
     is_speaking: bool,
-    speech_timings: *mut Vec<SpeechTiming<'static>>,
+    expressions: *mut Vec<Expression<'static>>,
+    current_expression: usize,
+    default_expression: usize,
     current_timing: Option<*const SpeechTiming<'static>>,
     requires_update: bool,
     should_hover: bool,
@@ -64,24 +113,121 @@ struct SharedData {
     input_device_name: String,
     input_device_index: usize,
     input_devices: *mut Vec<Device>,
-    current_max_frames: i32,
-    current_frame: i32,
+    bounce_time: f32,
     host: Host,
     input_device: Option<Device>,
     background_color: Vector3<f32>,
     audio_data: *mut SharedAudioData,
     audio_thread: Option<Stream>,
     total_audio_time: u128,
-    time_active: f32
+    time_active: f32,
+    is_blinking: bool,
+    blink_timer: f32,
+    animation_frame: usize,
+    animation_time: f32,
+    previous_timing: Option<*const SpeechTiming<'static>>,
+    previous_animation_frame: usize,
+    transition_time: f32,
+    physics: PhysicsState,
+    wobble_time: f32,
+    idle: IdleSettings,
+    idle_state: IdleState,
+    // in milliseconds since startup, an f64 so it doesn't lose precision over a long stream
+    parameter_time: f64,
+    held_keys: Vec<Keycode>,
+    // Relative to the window, but it can be outside of it
+    cursor_position: (i32, i32),
+    poke: PokeSettings,
+    poke_state: PokeState,
+    actions: Vec<Action<'static>>,
+    action_state: ActionState,
+    control_port: u16,
+    control: Option<Receiver<String>>,
+    emitters: Vec<Emitter<'static>>,
+    particles: Vec<Particle>,
+    // Names of actions that just started, for emitters to burst on
+    pending_bursts: Vec<String>,
+    caption: CaptionSettings,
+    caption_state: CaptionState,
+    // Whatever's typed into the properties window, before it gets shown
+    caption_input: String,
+    lipsync: LipSyncSettings,
+    lipsync_state: LipSyncState,
+    lipsync_input: String,
+    output_device_name: String,
+    output_devices: Vec<String>,
+    audio_output: Option<AudioOutput>,
+    tts: TtsSettings,
+    tts_state: TtsState,
+    sounds: Vec<SoundEffect>,
+    sleep: SleepSettings,
+    sleep_state: SleepState,
+    // Paths to Rhai scripts
+    scripts: Vec<String>,
+    script_state: ScriptState,
+    plugin: PluginSettings,
+    plugin_state: PluginState,
+    transparency_mode: TransparencyMode,
+    transparency: Box<dyn WindowTransparency>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct SavedData {
     input_device: String,
+    // Only read from older save files, before expressions were a thing.
+    #[serde(default, skip_serializing)]
     speech_timings: Vec<SavedSpeechData>,
+    #[serde(default)]
+    expressions: Vec<SavedExpressionData>,
+    #[serde(default)]
+    default_expression: String,
     key_r: f32,
     key_g: f32,
-    key_b: f32
+    key_b: f32,
+    #[serde(default)]
+    idle: IdleSettings,
+    #[serde(default)]
+    poke: PokeSettings,
+    #[serde(default)]
+    actions: Vec<ActionSettings>,
+    // Port for the local control interface, 0 turns it off
+    #[serde(default)]
+    control_port: u16,
+    #[serde(default)]
+    emitters: Vec<EmitterSettings>,
+    #[serde(default)]
+    caption: CaptionSettings,
+    #[serde(default)]
+    lipsync: LipSyncSettings,
+    // Empty uses the default output device
+    #[serde(default)]
+    output_device: String,
+    #[serde(default)]
+    tts: TtsSettings,
+    #[serde(default)]
+    sounds: Vec<SoundEffectSettings>,
+    #[serde(default)]
+    sleep: SleepSettings,
+    #[serde(default)]
+    scripts: Vec<String>,
+    #[serde(default)]
+    plugin: PluginSettings,
+    #[serde(default)]
+    transparency: TransparencyMode
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SavedExpressionData {
+    name: String,
+    #[serde(default)]
+    hotkey: String,
+    speech_timings: Vec<SavedSpeechData>,
+    #[serde(default)]
+    blink: Option<SavedBlinkData>,
+    #[serde(default)]
+    layers: Vec<SavedLayerData>,
+    #[serde(default)]
+    parameters: Vec<ParameterSettings>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -89,27 +235,76 @@ struct SavedSpeechData {
     threshold: f32,
     attack_time: f32,
     release_time: f32,
+    // Only read from older save files, before timings could have more than one image.
+    #[serde(default, skip_serializing)]
     texture_path: String,
+    #[serde(default)]
+    images: Vec<ImageVariant>,
+    #[serde(default)]
+    variant_mode: VariantMode,
     height_reduction: i32,
 
     should_bounce: bool,
-    max_velocity: f32,
-    total_velocity_frames: i32
+    // Only read from older save files, bounce used to be measured in frames.
+    #[serde(default, skip_serializing)]
+    max_velocity: Option<f32>,
+    #[serde(default, skip_serializing)]
+    total_velocity_frames: Option<i32>,
+    #[serde(default)]
+    bounce: Option<BounceSettings>,
+
+    #[serde(default)]
+    animation: AnimationSettings,
+    #[serde(default)]
+    transition: TransitionSettings,
+    #[serde(default)]
+    spring: SpringSettings,
+    #[serde(default)]
+    wobble: WobbleSettings,
+    #[serde(default)]
+    mesh: MeshSettings
 }
 
 struct SpeechTiming<'a> {
     threshold: f32,
     attack_time: f32,
     release_time: f32,
-    texture_path: String,
-    texture_surface: Surface<'a>,
-    texture: Texture,
-    max_velocity: f32,
+    // Never empty, one of these gets picked each time the timing is entered
+    images: Vec<TimingImage<'a>>,
+    variant_mode: VariantMode,
+    current_image: Cell<usize>,
+    // Where round robin picks up from
+    next_image: Cell<usize>,
+    animation_settings: AnimationSettings,
+    transition: TransitionSettings,
+    spring: SpringSettings,
+    wobble: WobbleSettings,
+    mesh: MeshSettings,
     should_bounce: bool,
-    total_velocity_frames: i32,
+    bounce: BounceSettings,
     height_reduction: i32,
 }
 
+impl SpeechTiming<'_> {
+    fn image(&self) -> &TimingImage<'_> {
+        &self.images[self.current_image.get().min(self.images.len() - 1)]
+    }
+
+    fn pick_image(&self) {
+        self.current_image.set(pick_variant(self.variant_mode, &self.images, &self.next_image));
+    }
+}
+
+struct Expression<'a> {
+    name: String,
+    // SDL key name, as given by Keycode::name(). Empty if unbound.
+    hotkey: String,
+    speech_timings: Vec<SpeechTiming<'a>>,
+    blink: BlinkLayer<'a>,
+    layers: Vec<AvatarLayer<'a>>,
+    parameters: Vec<Parameter<'a>>
+}
+
 fn str_to_c(text: &str) -> *const c_char {
     return CString::new(text).unwrap().as_c_str().as_ptr();
 }
@@ -133,25 +328,66 @@ fn save(shared_data: &mut SharedData) {
     let mut saved_data = SavedData {
         input_device: shared_data.input_device_name.clone(),
         speech_timings: Vec::new(),
+        expressions: Vec::new(),
+        default_expression: String::new(),
         key_r: shared_data.background_color.x,
         key_g: shared_data.background_color.y,
-        key_b: shared_data.background_color.z
+        key_b: shared_data.background_color.z,
+        idle: shared_data.idle.clone(),
+        poke: shared_data.poke.clone(),
+        actions: Vec::new(),
+        control_port: shared_data.control_port,
+        emitters: Vec::new(),
+        caption: shared_data.caption.clone(),
+        lipsync: shared_data.lipsync.clone(),
+        output_device: shared_data.output_device_name.clone(),
+        tts: shared_data.tts.clone(),
+        sounds: Vec::new(),
+        sleep: shared_data.sleep.clone(),
+        scripts: shared_data.scripts.clone(),
+        plugin: shared_data.plugin.clone(),
+        transparency: shared_data.transparency_mode
     };
 
-    for (i, timing) in unsafe { (*shared_data.speech_timings).iter().clone() }.enumerate() {
-        let speech_timing = SavedSpeechData {
-            threshold: timing.threshold,
-            attack_time: timing.attack_time,
-            release_time: timing.release_time,
-            texture_path: timing.texture_path.clone(),
-
-            should_bounce: timing.should_bounce,
-            max_velocity: timing.max_velocity,
-            total_velocity_frames: timing.total_velocity_frames,
-            height_reduction: timing.height_reduction
+    for emitter in shared_data.emitters.iter() {
+        saved_data.emitters.push(emitter.settings.clone());
+    }
+
+    for sound in shared_data.sounds.iter() {
+        saved_data.sounds.push(sound.settings.clone());
+    }
+
+    for action in shared_data.actions.iter() {
+        saved_data.actions.push(action.settings.clone());
+    }
+
+    for (i, expression) in unsafe { (*shared_data.expressions).iter() }.enumerate() {
+        let mut saved_expression = SavedExpressionData {
+            name: expression.name.clone(),
+            hotkey: expression.hotkey.clone(),
+            speech_timings: Vec::new(),
+            blink: Some(expression.blink.save()),
+            layers: Vec::new(),
+            parameters: Vec::new()
         };
 
-        saved_data.speech_timings.insert(i, speech_timing);
+        for parameter in expression.parameters.iter() {
+            saved_expression.parameters.push(parameter.settings.clone());
+        }
+
+        for layer in expression.layers.iter() {
+            saved_expression.layers.push(layer.save());
+        }
+
+        for (j, timing) in expression.speech_timings.iter().enumerate() {
+            saved_expression.speech_timings.insert(j, save_speech_timing(timing));
+        }
+
+        if i == shared_data.default_expression {
+            saved_data.default_expression = expression.name.clone();
+        }
+
+        saved_data.expressions.insert(i, saved_expression);
     }
 
     let serialized = serde_yaml::to_string(&saved_data);
@@ -166,6 +402,104 @@ fn save(shared_data: &mut SharedData) {
     file.close().unwrap();
 }
 
+fn save_speech_timing(timing: &SpeechTiming) -> SavedSpeechData {
+    SavedSpeechData {
+        threshold: timing.threshold,
+        attack_time: timing.attack_time,
+        release_time: timing.release_time,
+        texture_path: String::new(),
+        images: timing.images.iter().map(|image| image.save()).collect(),
+        variant_mode: timing.variant_mode,
+
+        should_bounce: timing.should_bounce,
+        max_velocity: None,
+        total_velocity_frames: None,
+        bounce: Some(timing.bounce.clone()),
+        height_reduction: timing.height_reduction,
+
+        animation: timing.animation_settings.clone(),
+        transition: timing.transition.clone(),
+        spring: timing.spring.clone(),
+        wobble: timing.wobble.clone(),
+        mesh: timing.mesh.clone()
+    }
+}
+
+fn load_image(shared_data: &SharedData, texture_path: &str) -> (Surface<'static>, Texture) {
+    let png_surface = Surface::from_file(texture_path).unwrap_or(create_missing_tex());
+    // i thought this was already in unsafe but okay
+    let png_texture = unsafe { (*shared_data.pngtuber_canvas).create_texture_from_surface(&png_surface).unwrap() };
+
+    (png_surface, png_texture)
+}
+
+fn load_speech_timing(shared_data: &mut SharedData, timing: &SavedSpeechData) -> SpeechTiming<'static> {
+    let mut variants = timing.images.clone();
+    if variants.is_empty() {
+        variants.push(ImageVariant {
+            texture_path: timing.texture_path.clone(), // thanks rust.
+            ..ImageVariant::default()
+        });
+    }
+
+    SpeechTiming {
+        threshold: timing.threshold,
+        attack_time: timing.attack_time,
+        release_time: timing.release_time,
+
+        images: variants.iter().map(|variant| TimingImage::load(shared_data, variant, &timing.animation)).collect(),
+        variant_mode: timing.variant_mode,
+        current_image: Cell::new(0),
+        next_image: Cell::new(0),
+        animation_settings: timing.animation.clone(),
+        transition: timing.transition.clone(),
+        spring: timing.spring.clone(),
+        wobble: timing.wobble.clone(),
+        mesh: timing.mesh.clone(),
+
+        should_bounce: timing.should_bounce,
+        bounce: match (&timing.bounce, timing.max_velocity, timing.total_velocity_frames) {
+            (Some(bounce), _, _) => bounce.clone(),
+            (None, Some(max_velocity), Some(total_velocity_frames)) => BounceSettings::from_legacy(max_velocity, total_velocity_frames),
+            _ => BounceSettings::default()
+        },
+        height_reduction: timing.height_reduction
+    }
+}
+
+fn load_blink(shared_data: &mut SharedData, blink: &SavedBlinkData) -> BlinkLayer<'static> {
+    let (png_surface, png_texture) = load_image(shared_data, blink.texture_path.as_str());
+
+    BlinkLayer {
+        enabled: blink.enabled,
+        texture_path: blink.texture_path.clone(),
+        texture_surface: png_surface,
+        texture: png_texture,
+        duration: blink.duration,
+        min_interval: blink.min_interval,
+        max_interval: blink.max_interval
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SavedWindowData {
+    transparency: TransparencyMode
+}
+
+/// The window's created before everything else gets loaded, so what it needs is read on its own.
+fn load_transparency_mode() -> TransparencyMode {
+    let contents = match std::fs::read_to_string("pngtuber_data.yml") {
+        Ok(contents) => contents,
+        Err(_) => return TransparencyMode::default()
+    };
+
+    match serde_yaml::from_str::<SavedWindowData>(contents.as_str()) {
+        Ok(saved_data) => saved_data.transparency,
+        Err(_) => TransparencyMode::default()
+    }
+}
+
 fn load(shared_data: &mut SharedData) {
     let file = File::open("pngtuber_data.yml");
 
@@ -190,33 +524,84 @@ fn load(shared_data: &mut SharedData) {
 
     shared_data.input_device_name = saved_data.input_device;
     shared_data.background_color = Vector3::from([saved_data.key_r, saved_data.key_g, saved_data.key_b]);
-    for (i, timing) in saved_data.speech_timings.iter().enumerate() {
-        let texture_path = timing.texture_path.clone();
-        let png_surface = Surface::from_file(texture_path).unwrap_or(create_missing_tex());
-        // i thought this was already in unsafe but okay
-        let png_texture = unsafe { (*shared_data.pngtuber_canvas).create_texture_from_surface(&png_surface).unwrap() };
+    shared_data.idle = saved_data.idle;
+    shared_data.poke = saved_data.poke;
+    reload_poke_image(shared_data);
+    shared_data.control_port = saved_data.control_port;
+    shared_data.caption = saved_data.caption;
+    shared_data.lipsync = saved_data.lipsync;
+    shared_data.output_device_name = saved_data.output_device;
+    shared_data.tts = saved_data.tts;
+    shared_data.sleep = saved_data.sleep;
+    shared_data.scripts = saved_data.scripts;
+    shared_data.plugin = saved_data.plugin;
+    shared_data.transparency_mode = saved_data.transparency;
+
+    for action in saved_data.actions.iter() {
+        let action = Action::load(shared_data, action);
+        shared_data.actions.push(action);
+    }
 
-        let speech_timing = SpeechTiming {
-            threshold: timing.threshold,
-            attack_time: timing.attack_time,
-            release_time: timing.release_time,
+    for emitter in saved_data.emitters.iter() {
+        let emitter = Emitter::load(shared_data, emitter);
+        shared_data.emitters.push(emitter);
+    }
 
-            texture_path: timing.texture_path.clone(), // thanks rust.
-            texture_surface: png_surface,
-            texture: png_texture,
+    for sound in saved_data.sounds.iter() {
+        shared_data.sounds.push(SoundEffect::load(sound));
+    }
+
+    // Older save files only have a single list of timings, so treat it as the default expression.
+    let mut saved_expressions = saved_data.expressions;
+    if saved_expressions.is_empty() && !saved_data.speech_timings.is_empty() {
+        saved_expressions.push(SavedExpressionData {
+            name: String::from("Default"),
+            hotkey: String::new(),
+            speech_timings: saved_data.speech_timings,
+            blink: None,
+            layers: Vec::new(),
+            parameters: Vec::new()
+        });
+    }
+
+    for (i, saved_expression) in saved_expressions.iter().enumerate() {
+        let blink = match &saved_expression.blink {
+            Some(saved_blink) => load_blink(shared_data, saved_blink),
+            None => create_default_blink(shared_data)
+        };
 
-            should_bounce: timing.should_bounce,
-            max_velocity: timing.max_velocity,
-            total_velocity_frames: timing.total_velocity_frames,
-            height_reduction: timing.height_reduction
+        let mut expression = Expression {
+            name: saved_expression.name.clone(),
+            hotkey: saved_expression.hotkey.clone(),
+            speech_timings: Vec::new(),
+            blink,
+            layers: Vec::new(),
+            parameters: Vec::new()
         };
 
-        let timings = shared_data.speech_timings;
+        for parameter in saved_expression.parameters.iter() {
+            expression.parameters.push(Parameter::load(shared_data, parameter));
+        }
+
+        for layer in saved_expression.layers.iter() {
+            expression.layers.push(AvatarLayer::load(shared_data, layer));
+        }
+
+        for (j, timing) in saved_expression.speech_timings.iter().enumerate() {
+            expression.speech_timings.insert(j, load_speech_timing(shared_data, timing));
+        }
+
+        if expression.name == saved_data.default_expression {
+            shared_data.default_expression = i;
+        }
+
         unsafe {
-            (*timings).insert(i, speech_timing);
+            (*shared_data.expressions).insert(i, expression);
         }
     }
 
+    shared_data.current_expression = shared_data.default_expression;
+
     file_thing.close().unwrap();
 }
 
@@ -229,15 +614,11 @@ fn main() {
     gl_attr.set_context_version(3, 3);
     gl_attr.set_context_profile(GLProfile::Core);
 
-    let font = ttf_context.load_font("C:/Windows/Fonts/ARIALN.TTF", 16).unwrap();
+    let font_path = find_font();
+    let font = ttf_context.load_font(font_path.as_str(), 16).unwrap();
 
-    let window = video_subsystem.window("Generic Title", 512, 512)
-        .position_centered()
-        .set_window_flags(SDL_WINDOW_SHOWN as u32)
-        .borderless()
-        .opengl()
-        .build()
-        .unwrap();
+    let transparency_mode = load_transparency_mode();
+    let (window, transparency) = create_window(&video_subsystem, "Generic Title", 512, 512, transparency_mode);
 
     let png_context = sdl2::image::init(InitFlag::all()).unwrap();
 
@@ -270,18 +651,63 @@ fn main() {
         current_level: 0.0,
         current_pitch: 0.0,
         should_exit: false,
+        output_level: -f32::INFINITY,
+        input_buffer_len: 0,
     };
 
     let mut data = SharedData {
         last_frame,
         current_velocity: 0.0,
-        current_max_velocity: 0.0,
-        current_frame: 0,
-        current_max_frames: 0,
+        bounce_time: 0.0,
         is_speaking: false,
-        speech_timings: &mut Vec::new(),
+        expressions: &mut Vec::new(),
+        current_expression: 0,
+        default_expression: 0,
         total_audio_time: 0u128,
         time_active: 0.0f32,
+        is_blinking: false,
+        blink_timer: 0.0f32,
+        animation_frame: 0,
+        animation_time: 0.0f32,
+        previous_timing: None,
+        previous_animation_frame: 0,
+        transition_time: 0.0f32,
+        physics: PhysicsState::new(),
+        wobble_time: 0.0f32,
+        idle: IdleSettings::default(),
+        idle_state: IdleState::new(),
+        parameter_time: 0.0f64,
+        held_keys: Vec::new(),
+        cursor_position: (0, 0),
+        poke: PokeSettings::default(),
+        poke_state: PokeState::new(),
+        actions: Vec::new(),
+        action_state: ActionState::new(),
+        control_port: 0,
+        control: None,
+        emitters: Vec::new(),
+        particles: Vec::new(),
+        pending_bursts: Vec::new(),
+        caption: CaptionSettings::default(),
+        caption_state: CaptionState::new(),
+        caption_input: String::new(),
+        lipsync: LipSyncSettings::default(),
+        lipsync_state: LipSyncState::new(),
+        lipsync_input: String::new(),
+        output_device_name: String::new(),
+        output_devices: Vec::new(),
+        audio_output: None,
+        tts: TtsSettings::default(),
+        tts_state: TtsState::new(),
+        sounds: Vec::new(),
+        sleep: SleepSettings::default(),
+        sleep_state: SleepState::new(),
+        scripts: Vec::new(),
+        script_state: ScriptState::new(),
+        plugin: PluginSettings::default(),
+        plugin_state: PluginState::new(),
+        transparency_mode,
+        transparency,
         current_timing: None,
         requires_update: true,
         should_render_props: false,
@@ -334,53 +760,53 @@ fn main() {
     }
 
     load(&mut data);
+
+    unsafe {
+        if (*data.expressions).is_empty() {
+            let expression = create_default_expression(&mut data);
+            (*data.expressions).push(expression);
+        }
+    }
+
     update_input_devices(&mut data);
+    data.output_devices = output_device_names(&data.host);
+
+    data.control = spawn_control_server(data.control_port);
+    load_plugins(&mut data);
+
+    let caption_font = ttf_context.load_font(font_path.as_str(), data.caption.font_size.max(1)).unwrap();
 
     let audio_thread = spawn_audio_handler(&mut data);
     data.audio_thread = Option::Some(audio_thread);
 
-    set_layered_window_attr(&mut canvas, &mut data);
+    apply_transparency(&mut canvas, &mut data);
 
     unsafe {
-        if (*data.speech_timings).is_empty() {
+        if (*current_timings(&data)).is_empty() {
             canvas.window_mut().set_bordered(true);
             data.is_bordered = true;
             data.should_open_props = true;
             data.is_props_open = true;
             data.should_render_props = true;
 
-            set_layered_window_attr(&mut canvas, &mut data);
+            apply_transparency(&mut canvas, &mut data);
         }
     }
 
     'running: loop {
-        if !render(&mut canvas, &mut event_pump, &font, &mut data) {
+        if !render(&mut canvas, &mut event_pump, &font, &caption_font, &mut data) {
             break 'running;
         }
     }
 }
 
-fn set_layered_window_attr(canvas: &mut WindowCanvas, data: &mut SharedData) {
-    unsafe {
-        if let RawWindowHandle::Win32(handle) = canvas.window().raw_window_handle() {
-            let hwnd: HWND = Handle::from_ptr(handle.hwnd);
-
-            let opacity = if data.is_bordered {
-                255
-            } else {
-                0
-            };
-
-            let add = if data.is_bordered {
-                1
-            } else {
-                0
-            };
+fn key_color(data: &SharedData) -> Color {
+    Color::RGB((data.background_color.x * 255.0) as u8, (data.background_color.y * 255.0) as u8, (data.background_color.z * 255.0) as u8)
+}
 
-            hwnd.SetWindowLongPtr(GWLP::EXSTYLE, hwnd.GetWindowLongPtr(GWLP::EXSTYLE) | (WS_EX::LAYERED.raw() as isize));
-            hwnd.SetLayeredWindowAttributes(COLORREF::new((data.background_color.x * 255.0) as u8, (data.background_color.y * 255.0) as u8, (data.background_color.z * 255.0) as u8), opacity, LWA::COLORKEY).unwrap();
-        }
-    }
+fn apply_transparency(canvas: &mut WindowCanvas, data: &mut SharedData) {
+    let key_color = key_color(data);
+    data.transparency.apply(canvas.window(), key_color, data.sleep_state.alpha);
 }
 
 fn update_input_devices(data: &mut SharedData) {
@@ -442,73 +868,189 @@ fn create_default_timing(data: &mut SharedData) -> SpeechTiming<'static> {
         threshold: 0.0,
         attack_time: 0.0,
         release_time: 0.0,
+        images: vec![TimingImage::load(data, &ImageVariant::default(), &AnimationSettings::default())],
+        variant_mode: VariantMode::default(),
+        current_image: Cell::new(0),
+        next_image: Cell::new(0),
+        animation_settings: AnimationSettings::default(),
+        transition: TransitionSettings::default(),
+        spring: SpringSettings::default(),
+        wobble: WobbleSettings::default(),
+        mesh: MeshSettings::default(),
+        should_bounce: false,
+        bounce: BounceSettings::default(),
+        height_reduction: 32
+    }
+}
+
+fn create_default_blink(data: &mut SharedData) -> BlinkLayer<'static> {
+    BlinkLayer {
+        enabled: false,
+        texture_path: String::from(""),
         texture_surface: create_missing_tex(),
         texture: unsafe {
             (*data.pngtuber_canvas).create_texture_from_surface(create_missing_tex())
         }.unwrap(),
-        max_velocity: 12.0,
-        should_bounce: false,
-        texture_path: String::from(""),
-        total_velocity_frames: 0,
-        height_reduction: 32
+        duration: 150.0,
+        min_interval: 2000.0,
+        max_interval: 6000.0
+    }
+}
+
+fn create_default_expression(data: &mut SharedData) -> Expression<'static> {
+    Expression {
+        name: String::from("Default"),
+        hotkey: String::new(),
+        speech_timings: Vec::new(),
+        blink: create_default_blink(data),
+        layers: Vec::new(),
+        parameters: Vec::new()
+    }
+}
+
+fn current_expression(data: &SharedData) -> *mut Expression<'static> {
+    unsafe {
+        &mut (&mut *data.expressions)[data.current_expression]
+    }
+}
+
+fn current_timings(data: &SharedData) -> *mut Vec<SpeechTiming<'static>> {
+    unsafe {
+        &mut (*current_expression(data)).speech_timings
+    }
+}
+
+fn switch_expression(data: &mut SharedData, index: usize) {
+    if index == data.current_expression || index >= unsafe { (*data.expressions).len() } {
+        return;
     }
+
+    // current_timing is left alone on purpose, so the old expression's timing
+    // still has to go through its release time before the new one takes over.
+    data.current_expression = index;
+    data.requires_update = true;
+
+    let name = unsafe { (&(*data.expressions))[index].name.clone() };
+    play_sounds_for_expression(data, name.as_str());
 }
 
 fn is_over_button(window_width: i32, x: i32, y: i32) -> bool {
     x > (window_width - 32) && x < window_width && y < 32 && y > 0
 }
 
+unsafe fn draw_timing(window_size: (u32, u32), data: &SharedData, timing: *const SpeechTiming, frame_index: usize, effect: &TransitionEffect) -> Rect {
+    let canvas = data.pngtuber_canvas;
+    let image = (*timing).image();
+    let surface = &image.texture_surface;
+
+    let (tex, source, width, height) = match image.animation.as_ref().and_then(|animation| animation.frame(frame_index)) {
+        Some((texture, frame)) => (texture, frame.source, frame.width, frame.height),
+        None => (&image.texture, None, surface.width(), surface.height())
+    };
+
+    let window_height = window_size.1 - ((*timing).height_reduction as u32);
+
+    let height_percent = (window_height as f64) / (height as f64);
+    let new_width = ((width as f64) * height_percent * effect.scale) as u32;
+    let new_height = ((window_height as f64) * effect.scale) as u32;
+
+    // Scaled from the bottom middle, so the avatar doesn't lift off the bottom of the window when it pops.
+    let x = ((window_size.0 as i32) / 2) - ((new_width as i32) / 2) + effect.offset_x;
+    let y = (window_size.1 as i32) - (new_height as i32) + effect.offset_y;
+    let dest = Rect::new(x, y, new_width, new_height);
+
+    // With layers, the timing's own texture is optional, so it's just another layer at the bottom.
+    let layers = &(*current_expression(data)).layers;
+    if !image.texture_path.is_empty() || layers.is_empty() {
+        // Parameters without a target apply to the whole avatar, which includes this.
+        let modifiers = parameter_modifiers(data, "");
+        let alpha = ((effect.alpha as f32) / 255.0) * modifiers.opacity;
+        let transform = avatar_transform(data);
+        let pivot = avatar_pivot(window_size, data);
+
+        match modifiers.crossfade {
+            Some((amount, other)) => {
+                copy_warped(canvas, tex, source, dest, &(*timing).mesh, data, &transform, pivot, (1.0 - amount) * alpha, effect.color);
+                copy_warped(canvas, &(*other).texture, None, dest, &(*timing).mesh, data, &transform, pivot, amount * alpha, effect.color);
+            }
+            None => copy_warped(canvas, tex, source, dest, &(*timing).mesh, data, &transform, pivot, alpha, effect.color)
+        }
+    }
+
+    dest
+}
+
 unsafe fn render_pngtuber(window_size: (u32, u32), data: &mut SharedData) {
     let canvas = (&data).pngtuber_canvas;
     if (&data).current_timing.is_none() {
         return;
     }
 
+    if render_action(window_size, data) || render_poke(window_size, data) {
+        return;
+    }
+
     let timing = *(&data).current_timing.as_ref().unwrap();
-    let surface = &(*timing).texture_surface;
-    let tex = &(*timing).texture;
+    let progress = transition_progress(data);
+
+    // Fade the old timing out underneath the new one.
+    if let Some(previous) = data.previous_timing {
+        if progress < 1.0 && (*timing).transition.crossfade {
+            draw_timing(window_size, data, previous, data.previous_animation_frame, &TransitionEffect::faded(1.0 - progress));
+        }
+    }
 
-    let width = surface.width();
-    let height = surface.height();
+    let dest = draw_timing(window_size, data, timing, data.animation_frame, &(*timing).transition.effect(progress));
 
     let window_height = window_size.1 - ((*timing).height_reduction as u32);
+    render_layers(window_size, window_height, data);
 
-    let height_percent = (window_height as f64) / (height as f64);
-    let new_width = ((width as f64) * height_percent) as u32;
-    (*canvas).copy(&tex, None, Option::from(Rect::new(((window_size.0 / 2) - new_width / 2) as i32, ((window_size.1 - window_height) + (data.current_velocity.round() as u32)) as i32, new_width, window_height))).unwrap();
+    // The blink overlay is drawn in the same spot, so it follows the bounce too.
+    let blink = &(*current_expression(data)).blink;
+    if data.is_blinking && blink.enabled {
+        copy_warped(canvas, &blink.texture, None, dest, &(*timing).mesh, data, &avatar_transform(data), avatar_pivot(window_size, data), 1.0, (255, 255, 255));
+    }
 }
 
 const NANOS_TO_MILLIS: f64 = 1e6;
-const MILLIS_TO_SECS: f64 = 1000f64;
 
 fn tick_pngtuber(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let timings = current_timings(data);
+    let level = current_level(data);
     let mut current_timing = &mut (*data).current_timing;
     let mut tracked_timing: Option<&SpeechTiming> = Option::None;
 
     unsafe {
-        for mut timing in (*data.speech_timings).iter() {
+        for mut timing in (*timings).iter() {
             if tracked_timing.is_none() {
-                if (*data.audio_data).current_level >= timing.threshold {
+                if level >= timing.threshold {
                     let _ = tracked_timing.insert(timing);
                 }
                 continue;
             }
 
             let current = tracked_timing.unwrap();
-            if timing.threshold <= (*data.audio_data).current_level && timing.threshold >= current.threshold {
+            if timing.threshold <= level && timing.threshold >= current.threshold {
                 let _ = tracked_timing.insert(timing);
             }
         }
 
+        // Scripts can hold the avatar on a timing, no matter the level.
+        if let Some(timing) = data.script_state.timing_override.and_then(|index| (&(*timings)).get(index)) {
+            let _ = tracked_timing.insert(timing);
+        }
+
         if tracked_timing.is_none() {
             return;
         }
 
-        if (&current_timing).is_some() && tracked_timing.unwrap().threshold != (*(&current_timing).unwrap()).threshold {
+        // Compared by identity rather than threshold, so a timing from another expression
+        // with the same threshold still counts as a state change.
+        if (&current_timing).is_some() && !std::ptr::eq(tracked_timing.unwrap(), (&current_timing).unwrap()) {
             data.total_audio_time += nanos_since_last_frame;
             data.time_active = 0.0;
             data.current_velocity = 0.0;
-            data.current_frame = 0;
+            data.bounce_time = 0.0;
         } else if data.time_active > 0.0 {
             data.total_audio_time = 0;
         }
@@ -517,37 +1059,65 @@ fn tick_pngtuber(data: &mut SharedData, nanos_since_last_frame: u128) {
 
         if (
             ((&current_timing).is_some() && ((
-                (*(&current_timing).unwrap()).threshold != tracked_timing.unwrap().threshold && (*(&current_timing).unwrap()).release_time <= total_time_millis
-            ) || (*(&current_timing).unwrap()).threshold == tracked_timing.unwrap().threshold)
+                !std::ptr::eq(tracked_timing.unwrap(), (&current_timing).unwrap()) && (*(&current_timing).unwrap()).release_time <= total_time_millis
+            ) || std::ptr::eq(tracked_timing.unwrap(), (&current_timing).unwrap()))
         ) || (&current_timing).is_none()) &&
             (tracked_timing.unwrap().attack_time <= total_time_millis || tracked_timing.unwrap().attack_time == 0.0)
             && data.time_active == 0.0
         {
             data.time_active += total_time_millis;
             data.total_audio_time = 0;
-            data.current_max_velocity = tracked_timing.unwrap().max_velocity as f64;
-            data.current_max_frames = tracked_timing.unwrap().total_velocity_frames;
+
+            // Re-entering the same timing shouldn't replay its transition, or pick another image.
+            if (&current_timing).is_some() && !std::ptr::eq(tracked_timing.unwrap(), (&current_timing).unwrap()) {
+                data.previous_timing = *current_timing;
+                data.previous_animation_frame = data.animation_frame;
+                data.transition_time = 0.0;
+                tracked_timing.unwrap().pick_image();
+            } else if (&current_timing).is_none() {
+                tracked_timing.unwrap().pick_image();
+            }
+
+            data.animation_frame = 0;
+            data.animation_time = 0.0;
+            data.wobble_time = 0.0;
             let _ = (*current_timing).insert(tracked_timing.unwrap());
             data.requires_update = true;
         }
 
-        if data.time_active > 0.0 && ((&current_timing).is_some()) && (*(&current_timing).unwrap()).should_bounce && data.current_frame < (*(&current_timing).unwrap()).total_velocity_frames {
-            data.current_frame += (1.0 / (((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) / MILLIS_TO_SECS)) as i32;
-            data.current_velocity = interpolate_velocity(data.current_max_velocity, data.current_frame, data.current_max_frames);
-            if data.current_frame > data.current_max_frames {
-                data.current_frame = data.current_max_frames;
-            }
-            data.requires_update = true;
+        data.is_speaking = match *current_timing {
+            Some(timing) => is_speaking_timing(timings, timing),
+            None => false
+        };
+
+        if data.is_speaking {
+            data.sleep_state.last_speech = Instant::now();
         }
     }
+
+    tick_bounce(data, nanos_since_last_frame);
+}
+
+/// The level the avatar reacts to, from the mic, TTS or text being lip synced, whichever is loudest.
+fn current_level(data: &SharedData) -> f32 {
+    let level = unsafe { (*data.audio_data).current_level.max((*data.audio_data).output_level) };
+
+    if data.lipsync_state.is_playing() {
+        level.max(data.lipsync_state.level())
+    } else {
+        level
+    }
 }
 
-fn interpolate_velocity(max_velocity: f64, current_frame: i32, max_frame: i32) -> f64 {
-    let frame_relative = (current_frame as f64) / (max_frame as f64);
-    return (2.0 * (1.0 - frame_relative) * frame_relative * (max_velocity * max_velocity));
+/// Anything above the quietest timing in the current expression counts as speaking.
+fn is_speaking_timing(timings: *mut Vec<SpeechTiming<'static>>, timing: *const SpeechTiming) -> bool {
+    unsafe {
+        let quietest = (*timings).iter().map(|t| t.threshold).fold(f32::INFINITY, f32::min);
+        (*timing).threshold > quietest
+    }
 }
 
-fn render(canvas: &mut WindowCanvas, event_pump: &mut EventPump, font: &Font, data: &mut SharedData) -> bool {
+fn render(canvas: &mut WindowCanvas, event_pump: &mut EventPump, font: &Font, caption_font: &Font, data: &mut SharedData) -> bool {
     let refresh_rate = 90;
 
     for event in event_pump.poll_iter() {
@@ -581,10 +1151,47 @@ fn render(canvas: &mut WindowCanvas, event_pump: &mut EventPump, font: &Font, da
                     window.set_bordered(data.is_bordered);
                     data.requires_update = true;
 
-                    set_layered_window_attr(canvas, data);
+                    apply_transparency(canvas, data);
+                } else if mouse_btn == MouseButton::Left && !data.is_props_open {
+                    start_poke(data);
                 }
             }
 
+            Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                // Don't steal keys while typing into the properties window.
+                if !data.is_props_open {
+                    if !data.held_keys.contains(&keycode) {
+                        data.held_keys.push(keycode);
+                    }
+
+                    data.script_state.pending_hotkeys.push(keycode.name());
+
+                    let mut target: Option<usize> = None;
+
+                    for (i, expression) in unsafe { (*data.expressions).iter() }.enumerate() {
+                        if !expression.hotkey.is_empty() && Keycode::from_name(expression.hotkey.as_str()) == Some(keycode) {
+                            target = Some(i);
+                            break;
+                        }
+                    }
+
+                    if let Some(i) = target {
+                        switch_expression(data, i);
+                    }
+
+                    let action = data.actions.iter()
+                        .position(|action| !action.settings.hotkey.is_empty() && Keycode::from_name(action.settings.hotkey.as_str()) == Some(keycode));
+
+                    if let Some(i) = action {
+                        start_action(data, i);
+                    }
+                }
+            }
+
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                data.held_keys.retain(|key| *key != keycode);
+            }
+
             Event::MouseMotion { x, y, .. } => {
                 let window_size = canvas.window().size();
                 let is_over = is_over_button(window_size.0 as i32, x, y) && !data.is_props_open;
@@ -610,10 +1217,36 @@ fn render(canvas: &mut WindowCanvas, event_pump: &mut EventPump, font: &Font, da
         data.requires_update = true;
     }*/
 
+    poll_control(data);
+
     let current_frame = SystemTime::now();
     let last_frame_time = SystemTime::now().duration_since(data.last_frame).unwrap();
 
+    tick_tts(data);
+    tick_lipsync(data, last_frame_time.as_nanos());
     tick_pngtuber(data, last_frame_time.as_nanos());
+    tick_sleep(data, last_frame_time.as_nanos());
+    tick_scripts(data, last_frame_time.as_nanos());
+    tick_plugins(data, last_frame_time.as_nanos());
+    tick_blink(data, last_frame_time.as_nanos());
+    tick_animation(data, last_frame_time.as_nanos());
+    tick_transition(data, last_frame_time.as_nanos());
+    tick_physics(data, last_frame_time.as_nanos());
+    tick_wobble(data, last_frame_time.as_nanos());
+    tick_idle(data, last_frame_time.as_nanos());
+    tick_parameters(data, last_frame_time.as_nanos());
+    tick_poke(data, last_frame_time.as_nanos());
+    tick_actions(data, last_frame_time.as_nanos());
+
+    data.cursor_position = global_cursor_position(canvas.window().position());
+    tick_eye_tracking(data, canvas.window().size(), last_frame_time.as_nanos());
+    tick_particles(data, canvas.window().size(), last_frame_time.as_nanos());
+    tick_captions(data, last_frame_time.as_nanos());
+
+    if data.sleep_state.is_alpha_dirty {
+        data.sleep_state.is_alpha_dirty = false;
+        apply_transparency(canvas, data);
+    }
 
     if DEBUG_ALWAYS_UPDATE {
         data.requires_update = true;
@@ -630,7 +1263,7 @@ fn render(canvas: &mut WindowCanvas, event_pump: &mut EventPump, font: &Font, da
         data.requires_update = false;
     }
 
-    canvas.set_draw_color(Color::RGBA((data.background_color.x * 255.0) as u8, (data.background_color.y * 255.0) as u8, (data.background_color.z * 255.0) as u8, 0));
+    canvas.set_draw_color(data.transparency.clear_color(key_color(data)));
     canvas.clear();
 
     unsafe {
@@ -643,8 +1276,17 @@ fn render(canvas: &mut WindowCanvas, event_pump: &mut EventPump, font: &Font, da
     // Delegated to a separate func, for organization purposes
     unsafe {
         render_pngtuber(window_size, data);
+        render_particles(data);
+        render_captions(window_size, data, caption_font);
     }
 
+    let shape = FrameShape {
+        timing: data.current_timing.map_or(0, |timing| timing as usize),
+        bounce_offset: data.current_velocity,
+        is_full: data.is_props_open || data.should_render_props
+    };
+    data.transparency.update_frame(canvas.window(), unsafe { (*data.pngtuber_canvas).surface() }, shape);
+
     let pngtuber_tex = canvas.create_texture_from_surface(unsafe { (*data.pngtuber_canvas).surface() }).unwrap();
 
     canvas.copy(&pngtuber_tex, None, None).unwrap();
@@ -828,6 +1470,26 @@ fn render(canvas: &mut WindowCanvas, event_pump: &mut EventPump, font: &Font, da
     true
 }
 
+unsafe fn reload_timing_image(data: &mut SharedData, timing: &mut SpeechTiming<'static>) {
+    for image in timing.images.iter_mut() {
+        image.destroy();
+        *image = TimingImage::load(data, &image.save(), &timing.animation_settings);
+    }
+
+    data.animation_frame = 0;
+    data.animation_time = 0.0;
+    data.requires_update = true;
+}
+
+fn pick_image_file() -> Option<String> {
+    let file = FileDialog::new()
+        .add_filter("Image files", &["png", "apng", "gif", "webp"])
+        .set_title("Select Image File")
+        .pick_file();
+
+    file.map(|path| String::from(path.to_str().unwrap()))
+}
+
 unsafe fn render_ui(canvas: &mut WindowCanvas, ui: &mut Ui, data: &mut SharedData) -> bool {
     let window = ui.window("Properties")
         .size(Vector2::from([ 420.0, 356.0 ]), Condition::Always)
@@ -838,8 +1500,6 @@ unsafe fn render_ui(canvas: &mut WindowCanvas, ui: &mut Ui, data: &mut SharedDat
         .begin();
 
     if window.is_some() {
-        let timings = data.speech_timings;
-
         if ui.button("Close Properties") {
             data.is_props_open = false;
             save(data);
@@ -886,96 +1546,1569 @@ unsafe fn render_ui(canvas: &mut WindowCanvas, ui: &mut Ui, data: &mut SharedDat
             c.end();
         }
 
+        ui.text("Output Device");
+        ui.same_line();
+
+        let output_label = if data.output_device_name.is_empty() {
+            String::from("(default)")
+        } else {
+            data.output_device_name.clone()
+        };
+
+        if let Some(_combo) = ui.begin_combo("##output_device", output_label) {
+            if ui.selectable("(default)") {
+                data.output_device_name = String::new();
+                data.audio_output = None;
+            }
+
+            for name in data.output_devices.clone() {
+                if ui.selectable(name.clone()) {
+                    data.output_device_name = name.clone();
+                    // Reopened on the next sound that plays
+                    data.audio_output = None;
+                }
+
+                if name == data.output_device_name {
+                    ui.set_item_default_focus();
+                }
+            }
+        }
+
+        ui.same_line();
+
+        if ui.button("Refresh##output_device_refresh") {
+            data.output_devices = output_device_names(&data.host);
+        }
+
+        ui.text(format!("Transparency: {}", data.transparency.name()));
+
+        ui.text("Window Mode (applies after a restart)");
+        if let Some(_combo) = ui.begin_combo("##transparency_mode", data.transparency_mode.name()) {
+            for mode in TransparencyMode::ALL {
+                if ui.selectable(mode.name()) {
+                    data.transparency_mode = mode;
+                }
+            }
+        }
+
         let group = ui.begin_group();
 
-        if ui.collapsing_header("Change Keying Color", TreeNodeFlags::empty()) {
+        if data.transparency.uses_key_color() && ui.collapsing_header("Change Keying Color", TreeNodeFlags::empty()) {
             if ui.color_picker3_config("##color", &mut data.background_color)
                 .alpha(false)
                 .build() {
-                set_layered_window_attr(canvas, data);
+                apply_transparency(canvas, data);
             }
         }
 
         group.end();
 
-        if ui.button("Add Timing") {
-            (*timings).insert((*timings).len(), create_default_timing(data));
+        let group = ui.begin_group();
+
+        if ui.collapsing_header("Idle Motion", TreeNodeFlags::empty()) {
+            ui.indent_by(4.0);
+
+            let idle = &mut data.idle;
+            ui.checkbox("Enabled?##idle_enabled", &mut idle.enabled);
+
+            if idle.enabled {
+                ui.text("Breathing Amount / Period (ms)");
+                ui.slider("##idle_breathing_amount", 0.0, 0.1, &mut idle.breathing_amount);
+                ui.slider("##idle_breathing_period", 500.0, 10000.0, &mut idle.breathing_period);
+
+                ui.text("Sway Amount (px) / Period (ms)");
+                ui.slider("##idle_sway_amount", 0.0, 32.0, &mut idle.sway_amount);
+                ui.slider("##idle_sway_period", 500.0, 10000.0, &mut idle.sway_period);
+
+                ui.text("Idle Frame Rate");
+                ui.slider("##idle_fps", 1.0, 60.0, &mut idle.fps);
+            }
+
+            ui.unindent_by(4.0);
         }
 
-        for (id, timing) in (*timings).iter_mut().enumerate() {
-            let group = ui.begin_group();
+        group.end();
 
-            if ui.collapsing_header(format!("Timing #{}##{}_group", id + 1, id), TreeNodeFlags::empty()) {
-                ui.indent_by(4.0);
-                if ui.button(format!("Remove##{}_remove", id)) {
-                    (*data.speech_timings).remove(id);
-                }
+        let group = ui.begin_group();
 
-                ui.spacing();
+        if ui.collapsing_header("Sleep", TreeNodeFlags::empty()) {
+            ui.indent_by(4.0);
 
-                ui.checkbox(format!("Should Bounce?##{}_bounce", id), &mut timing.should_bounce);
+            let sleep = &mut data.sleep;
+            ui.checkbox("Enabled?##sleep_enabled", &mut sleep.enabled);
 
-                ui.text("Threshold (dB)");
-                ui.slider(format!("##{}_threshold", id), -30.0, 30.0, &mut timing.threshold);
+            if sleep.enabled {
+                ui.text("Sleeping Expression (empty to keep the current one)");
+                ui.input_text("##sleep_expression", &mut sleep.expression)
+                    .build();
 
-                ui.text("Attack (ms)");
-                ui.slider(format!("##{}_attack", id), 0.0, 350.0, &mut timing.attack_time);
+                ui.text("Fall Asleep After (minutes)");
+                ui.slider("##sleep_after", 0.5, 60.0, &mut sleep.sleep_after);
 
-                ui.text("Release (ms)");
-                ui.slider(format!("##{}_release", id), 0.0, 350.0, &mut timing.release_time);
+                ui.checkbox("Fade Out?##sleep_fade_out", &mut sleep.fade_out);
 
-                if timing.should_bounce {
-                    ui.text("Total Bounce Frames");
-                    ui.slider(format!("##{}_velocity_frames", id), 0, 600, &mut timing.total_velocity_frames);
+                if sleep.fade_out {
+                    ui.text("Fade Out After (minutes)");
+                    ui.slider("##sleep_fade_after", 0.5, 120.0, &mut sleep.fade_after);
 
-                    ui.text("Max Bounce Velocity");
-                    ui.slider(format!("##{}_max_velocity", id), 0.0, 64.0, &mut timing.max_velocity);
+                    ui.text("Fade Out / In Time (ms)");
+                    ui.slider("##sleep_fade_out_time", 0.0, 10000.0, &mut sleep.fade_out_time);
+                    ui.slider("##sleep_fade_in_time", 0.0, 5000.0, &mut sleep.fade_in_time);
                 }
+            }
 
-                ui.text("Texture Path");
-                ui.input_text(format!("##{}_tex_path", id), &mut timing.texture_path)
-                    .build();
+            ui.unindent_by(4.0);
+        }
 
-                ui.same_line();
+        group.end();
 
-                if ui.button(format!("Open Path##{}_open_path", id)) {
-                    let file = FileDialog::new()
-                        .add_filter("Image files", &["png", "webp"])
-                        .set_title("Select Image File")
-                        .pick_file();
+        let group = ui.begin_group();
 
-                    if file.is_some() {
-                        let file_path = file.as_ref().unwrap().to_str().unwrap();
+        if ui.collapsing_header("Control Interface", TreeNodeFlags::empty()) {
+            ui.indent_by(4.0);
 
-                        timing.texture_path = String::from(file_path);
+            let mut port = data.control_port as i32;
+            ui.text("Port (0 = off, applies after a restart)");
+            if ui.input_int("##control_port", &mut port).build() {
+                data.control_port = port.clamp(0, u16::MAX as i32) as u16;
+            }
 
-                        drop(timing.texture_surface.context());
-                        unsafe {
-                            // this works better than the Rust destroy because Rust is too safe.
-                            SDL_DestroyTexture(timing.texture.raw());
-                        }
+            ui.text_wrapped("Send lines like \"action Wave\" or \"expression Happy\" to 127.0.0.1 on this port.");
+
+            ui.unindent_by(4.0);
+        }
+
+        group.end();
+
+        render_captions_ui(ui, data);
+        render_lipsync_ui(ui, data);
+        render_tts_ui(ui, data);
+
+        let group = ui.begin_group();
+
+        if ui.collapsing_header("Poke", TreeNodeFlags::empty()) {
+            ui.indent_by(4.0);
 
-                        let png_surface = Surface::from_file(file_path).unwrap_or(create_missing_tex());
-                        // i thought this was already in unsafe but okay
-                        let png_texture = unsafe { (*data.pngtuber_canvas).create_texture_from_surface(&png_surface).unwrap() };
+            ui.checkbox("Enabled?##poke_enabled", &mut data.poke.enabled);
+
+            if data.poke.enabled {
+                ui.text("Reaction Sprite (optional)");
+                ui.text(data.poke.texture_path.as_str());
+                ui.same_line();
 
-                        timing.texture_surface = png_surface;
-                        timing.texture = png_texture;
+                if ui.button("Open Path##poke_open_path") {
+                    if let Some(file_path) = pick_image_file() {
+                        data.poke.texture_path = file_path;
+                        reload_poke_image(data);
                     }
                 }
 
-                ui.text("Height Reduction");
-                ui.slider(format!("##{}_height_reduce", id), 0, 500, &mut timing.height_reduction);
+                if !data.poke.texture_path.is_empty() {
+                    ui.same_line();
 
-                ui.spacing();
-                ui.spacing();
-            }
+                    if ui.button("Clear##poke_clear") {
+                        data.poke.texture_path = String::new();
+                        reload_poke_image(data);
+                    }
+                }
 
-            group.end();
-        }
+                ui.text("Sprite Duration (ms)");
+                ui.slider("##poke_duration", 0.0, 3000.0, &mut data.poke.duration);
 
-        window.unwrap().end();
-    }
+                let bounce = &mut data.poke.bounce;
 
-    true
-}
\ No newline at end of file
+                ui.text("Bounce Height (px) / Duration (ms)");
+                ui.slider("##poke_bounce_height", -256.0, 256.0, &mut bounce.height);
+                ui.slider("##poke_bounce_duration", 0.0, 2000.0, &mut bounce.duration);
+
+                ui.text("Bounce Curve");
+                if let Some(_combo) = ui.begin_combo("##poke_bounce_curve", bounce.curve.name()) {
+                    for curve in BounceCurve::ALL {
+                        if ui.selectable(curve.name()) {
+                            bounce.curve = curve;
+                        }
+                    }
+                }
+
+                if ui.button("Preview##poke_preview") {
+                    start_poke(data);
+                }
+            }
+
+            ui.unindent_by(4.0);
+        }
+
+        group.end();
+
+        let group = ui.begin_group();
+
+        if ui.collapsing_header("Expressions", TreeNodeFlags::empty()) {
+            ui.indent_by(4.0);
+
+            let expressions = data.expressions;
+            let mut removed_expression: Option<usize> = None;
+
+            for (id, expression) in (*expressions).iter_mut().enumerate() {
+                let label = if id == data.current_expression {
+                    format!("> {}##{}_expression_switch", expression.name, id)
+                } else {
+                    format!("{}##{}_expression_switch", expression.name, id)
+                };
+
+                if ui.button(label) {
+                    switch_expression(data, id);
+                }
+
+                ui.same_line();
+
+                if id == data.default_expression {
+                    ui.text("(default)");
+                } else if ui.button(format!("Set Default##{}_expression_default", id)) {
+                    data.default_expression = id;
+                }
+
+                if (*expressions).len() > 1 {
+                    ui.same_line();
+
+                    if ui.button(format!("Remove##{}_expression_remove", id)) {
+                        removed_expression = Some(id);
+                    }
+                }
+
+                ui.text("Name");
+                ui.same_line();
+                ui.input_text(format!("##{}_expression_name", id), &mut expression.name)
+                    .build();
+
+                ui.text("Hotkey");
+                ui.same_line();
+                ui.input_text(format!("##{}_expression_hotkey", id), &mut expression.hotkey)
+                    .hint("e.g. F1")
+                    .build();
+
+                ui.checkbox(format!("Blink?##{}_blink", id), &mut expression.blink.enabled);
+
+                if expression.blink.enabled {
+                    let blink = &mut expression.blink;
+
+                    ui.text("Blink Texture Path");
+                    ui.input_text(format!("##{}_blink_tex_path", id), &mut blink.texture_path)
+                        .build();
+
+                    ui.same_line();
+
+                    if ui.button(format!("Open Path##{}_blink_open_path", id)) {
+                        if let Some(file_path) = pick_image_file() {
+                            blink.texture_path = file_path;
+
+                            drop(blink.texture_surface.context());
+                            SDL_DestroyTexture(blink.texture.raw());
+
+                            let (png_surface, png_texture) = load_image(data, blink.texture_path.as_str());
+                            blink.texture_surface = png_surface;
+                            blink.texture = png_texture;
+                            data.requires_update = true;
+                        }
+                    }
+
+                    ui.text("Blink Duration (ms)");
+                    ui.slider(format!("##{}_blink_duration", id), 0.0, 1000.0, &mut blink.duration);
+
+                    ui.text("Min Blink Interval (ms)");
+                    ui.slider(format!("##{}_blink_min_interval", id), 0.0, 20000.0, &mut blink.min_interval);
+
+                    ui.text("Max Blink Interval (ms)");
+                    ui.slider(format!("##{}_blink_max_interval", id), 0.0, 20000.0, &mut blink.max_interval);
+                }
+
+                ui.spacing();
+            }
+
+            if let Some(id) = removed_expression {
+                (*expressions).remove(id);
+
+                // Everything after the removed expression shifts down by one.
+                if data.default_expression >= id && data.default_expression > 0 {
+                    data.default_expression -= 1;
+                }
+
+                if data.current_expression >= id && data.current_expression > 0 {
+                    data.current_expression -= 1;
+                }
+
+                // The current timing may have belonged to the removed expression.
+                data.current_timing = None;
+                data.previous_timing = None;
+                data.time_active = 0.0;
+                data.requires_update = true;
+            }
+
+            if ui.button("Add Expression") {
+                let expression = create_default_expression(data);
+                (*expressions).push(expression);
+                // Pushing may have moved the timings in memory.
+                data.current_timing = None;
+                data.previous_timing = None;
+                data.time_active = 0.0;
+            }
+
+            ui.unindent_by(4.0);
+        }
+
+        group.end();
+
+        let timings = current_timings(data);
+
+        if ui.button("Add Timing") {
+            (*timings).insert((*timings).len(), create_default_timing(data));
+            // Inserting may have moved the timings in memory.
+            data.current_timing = None;
+            finish_transition(data);
+            data.time_active = 0.0;
+        }
+
+        for (id, timing) in (*timings).iter_mut().enumerate() {
+            let group = ui.begin_group();
+
+            if ui.collapsing_header(format!("Timing #{}##{}_group", id + 1, id), TreeNodeFlags::empty()) {
+                ui.indent_by(4.0);
+                if ui.button(format!("Remove##{}_remove", id)) {
+                    (*timings).remove(id);
+
+                    // Everything after the removed timing moved, including whatever's being shown or faded from.
+                    data.current_timing = None;
+                    finish_transition(data);
+                    data.time_active = 0.0;
+                    data.requires_update = true;
+
+                    // Keep the per-timing layer images lined up with the timings.
+                    for layer in (*current_expression(data)).layers.iter_mut() {
+                        if id < layer.timing_images.len() {
+                            if let Some(image) = layer.timing_images.remove(id) {
+                                image.destroy();
+                            }
+                        }
+                    }
+                }
+
+                ui.spacing();
+
+                ui.checkbox(format!("Should Bounce?##{}_bounce", id), &mut timing.should_bounce);
+
+                ui.text("Threshold (dB)");
+                ui.slider(format!("##{}_threshold", id), -30.0, 30.0, &mut timing.threshold);
+
+                ui.text("Attack (ms)");
+                ui.slider(format!("##{}_attack", id), 0.0, 350.0, &mut timing.attack_time);
+
+                ui.text("Release (ms)");
+                ui.slider(format!("##{}_release", id), 0.0, 350.0, &mut timing.release_time);
+
+                if timing.should_bounce {
+                    let bounce = &mut timing.bounce;
+
+                    ui.text("Bounce Height (px)");
+                    ui.slider(format!("##{}_bounce_height", id), -256.0, 256.0, &mut bounce.height);
+
+                    ui.text("Bounce Duration (ms)");
+                    ui.slider(format!("##{}_bounce_duration", id), 0.0, 2000.0, &mut bounce.duration);
+
+                    ui.text("Bounce Curve");
+                    if let Some(_combo) = ui.begin_combo(format!("##{}_bounce_curve", id), bounce.curve.name()) {
+                        for curve in BounceCurve::ALL {
+                            if ui.selectable(curve.name()) {
+                                bounce.curve = curve;
+                            }
+                        }
+                    }
+
+                    let preview: Vec<f32> = (0..64).map(|i| bounce.curve.evaluate((i as f32) / 63.0)).collect();
+                    ui.plot_lines(format!("##{}_bounce_preview", id), &preview)
+                        .graph_size([0.0, 48.0])
+                        .scale_min(-1.0)
+                        .scale_max(1.0)
+                        .build();
+
+                    ui.same_line();
+
+                    // The bounce only plays on the timing that's showing, so previewing any other one wouldn't do anything.
+                    let is_current = data.current_timing.is_some_and(|current| std::ptr::eq(current, timing));
+                    let disabled = ui.begin_disabled(!is_current);
+
+                    if ui.button(format!("Preview##{}_bounce_preview_play", id)) {
+                        data.bounce_time = 0.0;
+                        data.requires_update = true;
+                    }
+
+                    disabled.end();
+
+                    if !is_current && ui.is_item_hovered_with_flags(ItemHoveredFlags::ALLOW_WHEN_DISABLED) {
+                        ui.tooltip_text("Only the timing that's currently showing can be previewed");
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Images##{}_images", id)) {
+                    let mut removed_image: Option<usize> = None;
+                    let mut reload = false;
+                    let image_count = timing.images.len();
+
+                    if image_count > 1 {
+                        ui.text("Pick Images By");
+                        if let Some(_combo) = ui.begin_combo(format!("##{}_variant_mode", id), timing.variant_mode.name()) {
+                            for mode in VariantMode::ALL {
+                                if ui.selectable(mode.name()) {
+                                    timing.variant_mode = mode;
+                                }
+                            }
+                        }
+                    }
+
+                    for (image_id, image) in timing.images.iter_mut().enumerate() {
+                        ui.text(format!("Texture Path {}", image_id + 1));
+                        ui.input_text(format!("##{}_{}_tex_path", id, image_id), &mut image.texture_path)
+                            .build();
+
+                        ui.same_line();
+
+                        if ui.button(format!("Open Path##{}_{}_open_path", id, image_id)) {
+                            if let Some(file_path) = pick_image_file() {
+                                image.texture_path = file_path;
+                                reload = true;
+                            }
+                        }
+
+                        if image_count > 1 {
+                            ui.same_line();
+
+                            if ui.button(format!("Remove##{}_{}_image_remove", id, image_id)) {
+                                removed_image = Some(image_id);
+                            }
+
+                            if timing.variant_mode == VariantMode::Weighted {
+                                ui.slider(format!("Weight##{}_{}_image_weight", id, image_id), 0.0, 10.0, &mut image.weight);
+                            }
+                        }
+                    }
+
+                    if let Some(image_id) = removed_image {
+                        timing.images.remove(image_id).destroy();
+                        timing.current_image.set(0);
+                        data.requires_update = true;
+                    }
+
+                    if ui.button(format!("Add Image##{}_image_add", id)) {
+                        let image = TimingImage::load(data, &ImageVariant::default(), &timing.animation_settings);
+                        timing.images.push(image);
+                    }
+
+                    if reload {
+                        reload_timing_image(data, timing);
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Animation##{}_animation", id)) {
+                    let settings = &mut timing.animation_settings;
+
+                    ui.checkbox(format!("Loop?##{}_animation_loop", id), &mut settings.looping);
+
+                    ui.text("Sprite Sheet Columns / Rows");
+                    ui.slider(format!("##{}_sheet_columns", id), 1, 32, &mut settings.sheet_columns);
+                    ui.slider(format!("##{}_sheet_rows", id), 1, 32, &mut settings.sheet_rows);
+
+                    if settings.is_sprite_sheet() {
+                        ui.text("Sprite Sheet Frames (0 = all)");
+                        ui.slider(format!("##{}_sheet_frames", id), 0, 1024, &mut settings.sheet_frames);
+
+                        ui.text("Sprite Sheet FPS");
+                        ui.slider(format!("##{}_sheet_fps", id), 0.0, 60.0, &mut settings.sheet_fps);
+                    }
+
+                    if ui.button(format!("Reload Image##{}_reload", id)) {
+                        reload_timing_image(data, timing);
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Spring Physics##{}_spring", id)) {
+                    let spring = &mut timing.spring;
+
+                    ui.checkbox(format!("Enabled?##{}_spring_enabled", id), &mut spring.enabled);
+
+                    if spring.enabled {
+                        ui.text("Stiffness");
+                        ui.slider(format!("##{}_spring_stiffness", id), 1.0, 1000.0, &mut spring.stiffness);
+
+                        ui.text("Damping");
+                        ui.slider(format!("##{}_spring_damping", id), 0.0, 100.0, &mut spring.damping);
+
+                        ui.text("Offset / Rotation / Scale Impulse");
+                        ui.slider(format!("##{}_spring_offset_impulse", id), -2000.0, 2000.0, &mut spring.offset_impulse);
+                        ui.slider(format!("##{}_spring_rotation_impulse", id), -500.0, 500.0, &mut spring.rotation_impulse);
+                        ui.slider(format!("##{}_spring_scale_impulse", id), -5.0, 5.0, &mut spring.scale_impulse);
+
+                        ui.text("Peak Threshold (dB)");
+                        ui.slider(format!("##{}_spring_peak_threshold", id), 0.0, 30.0, &mut spring.peak_threshold);
+
+                        ui.text("Peak Strength");
+                        ui.slider(format!("##{}_spring_peak_strength", id), 0.0, 5.0, &mut spring.peak_strength);
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Squash & Wobble##{}_wobble", id)) {
+                    let wobble = &mut timing.wobble;
+
+                    ui.text("Scale X / Y");
+                    ui.slider(format!("##{}_wobble_scale_x", id), 0.1, 3.0, &mut wobble.scale_x);
+                    ui.slider(format!("##{}_wobble_scale_y", id), 0.1, 3.0, &mut wobble.scale_y);
+
+                    ui.text("Squash & Stretch Amount / Speed (Hz)");
+                    ui.slider(format!("##{}_wobble_squash", id), 0.0, 0.5, &mut wobble.squash);
+                    ui.slider(format!("##{}_wobble_squash_speed", id), 0.0, 20.0, &mut wobble.squash_speed);
+
+                    ui.text("Wobble Angle / Speed (Hz)");
+                    ui.slider(format!("##{}_wobble_angle", id), 0.0, 45.0, &mut wobble.wobble_angle);
+                    ui.slider(format!("##{}_wobble_speed", id), 0.0, 20.0, &mut wobble.wobble_speed);
+
+                    ui.text("Pivot X / Y");
+                    ui.slider(format!("##{}_wobble_pivot_x", id), 0.0, 1.0, &mut wobble.pivot_x);
+                    ui.slider(format!("##{}_wobble_pivot_y", id), 0.0, 1.0, &mut wobble.pivot_y);
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Mesh Warp##{}_mesh", id)) {
+                    if render_mesh_ui(ui, format!("{}_timing", id).as_str(), &mut timing.mesh) {
+                        data.requires_update = true;
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Transition##{}_transition", id)) {
+                    let transition = &mut timing.transition;
+
+                    ui.text("Duration (ms)");
+                    ui.slider(format!("##{}_transition_duration", id), 0.0, 1000.0, &mut transition.duration);
+
+                    ui.checkbox(format!("Crossfade?##{}_transition_crossfade", id), &mut transition.crossfade);
+
+                    ui.text("Pop Scale");
+                    ui.slider(format!("##{}_transition_pop", id), 0.0, 1.0, &mut transition.pop);
+
+                    ui.text("Slide X / Y");
+                    ui.slider(format!("##{}_transition_slide_x", id), -256, 256, &mut transition.slide_x);
+                    ui.slider(format!("##{}_transition_slide_y", id), -256, 256, &mut transition.slide_y);
+
+                    ui.checkbox(format!("Tint?##{}_transition_tint", id), &mut transition.tint);
+
+                    if transition.tint {
+                        ui.color_edit3(format!("##{}_transition_tint_color", id), &mut transition.tint_color);
+                    }
+                }
+
+                ui.text("Height Reduction");
+                ui.slider(format!("##{}_height_reduce", id), 0, 500, &mut timing.height_reduction);
+
+                ui.spacing();
+                ui.spacing();
+            }
+
+            group.end();
+        }
+
+        render_layers_ui(ui, data);
+        render_parameters_ui(ui, data);
+        render_actions_ui(ui, data);
+        render_particles_ui(ui, data);
+        render_sounds_ui(ui, data);
+        render_scripts_ui(ui, data);
+        render_plugins_ui(ui, data);
+
+        window.unwrap().end();
+    }
+
+    true
+}
+
+unsafe fn render_layers_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Layers", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        let layers = &mut (*current_expression(data)).layers;
+        let timing_count = (*current_timings(data)).len();
+        let mut removed_layer: Option<usize> = None;
+        let mut moved_layer: Option<(usize, usize)> = None;
+
+        for (id, layer) in layers.iter_mut().enumerate() {
+            if ui.collapsing_header(format!("{}##{}_layer_group", layer.name, id), TreeNodeFlags::empty()) {
+                ui.indent_by(4.0);
+
+                if ui.button(format!("Up##{}_layer_up", id)) && id > 0 {
+                    moved_layer = Some((id, id - 1));
+                }
+
+                ui.same_line();
+
+                if ui.button(format!("Down##{}_layer_down", id)) {
+                    moved_layer = Some((id, id + 1));
+                }
+
+                ui.same_line();
+
+                if ui.button(format!("Remove##{}_layer_remove", id)) {
+                    removed_layer = Some(id);
+                }
+
+                ui.text("Name");
+                ui.same_line();
+                ui.input_text(format!("##{}_layer_name", id), &mut layer.name)
+                    .build();
+
+                if ui.checkbox(format!("Follow Bounce?##{}_layer_bounce", id), &mut layer.follows_bounce) {
+                    data.requires_update = true;
+                }
+
+                ui.text("Offset X");
+                if ui.slider(format!("##{}_layer_offset_x", id), -256, 256, &mut layer.offset_x) {
+                    data.requires_update = true;
+                }
+
+                ui.text("Offset Y");
+                if ui.slider(format!("##{}_layer_offset_y", id), -256, 256, &mut layer.offset_y) {
+                    data.requires_update = true;
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Follow Cursor##{}_layer_tracking", id)) {
+                    let tracking = &mut layer.tracking;
+
+                    ui.checkbox(format!("Enabled?##{}_layer_tracking_enabled", id), &mut tracking.enabled);
+
+                    ui.text("Radius (px) / Reach (px)");
+                    ui.slider(format!("##{}_layer_tracking_radius", id), 0.0, 64.0, &mut tracking.radius);
+                    ui.slider(format!("##{}_layer_tracking_reach", id), 1.0, 2000.0, &mut tracking.reach);
+
+                    ui.text("Eye Position X / Y");
+                    ui.slider(format!("##{}_layer_tracking_anchor_x", id), 0.0, 1.0, &mut tracking.anchor_x);
+                    ui.slider(format!("##{}_layer_tracking_anchor_y", id), 0.0, 1.0, &mut tracking.anchor_y);
+
+                    ui.text("Smoothing (ms)");
+                    ui.slider(format!("##{}_layer_tracking_smoothing", id), 0.0, 500.0, &mut tracking.smoothing);
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Mesh Warp##{}_layer_mesh", id)) {
+                    if render_mesh_ui(ui, format!("{}_layer", id).as_str(), &mut layer.mesh) {
+                        data.requires_update = true;
+                    }
+                }
+
+                ui.text("Base Texture Path");
+                ui.text(layer.image.texture_path.as_str());
+                ui.same_line();
+
+                if ui.button(format!("Open Path##{}_layer_open_path", id)) {
+                    if let Some(file_path) = pick_image_file() {
+                        layer.image.destroy();
+                        layer.image = LayerImage::load(data, file_path.as_str());
+                        data.requires_update = true;
+                    }
+                }
+
+                for timing_id in 0..timing_count {
+                    if layer.timing_images.len() <= timing_id {
+                        layer.timing_images.resize_with(timing_id + 1, || None);
+                    }
+
+                    ui.text(format!("Timing #{}", timing_id + 1));
+                    ui.same_line();
+
+                    if ui.button(format!("Open Path##{}_{}_layer_timing_open_path", id, timing_id)) {
+                        if let Some(file_path) = pick_image_file() {
+                            if let Some(image) = &layer.timing_images[timing_id] {
+                                image.destroy();
+                            }
+
+                            layer.timing_images[timing_id] = Some(LayerImage::load(data, file_path.as_str()));
+                            data.requires_update = true;
+                        }
+                    }
+
+                    if let Some(image) = &layer.timing_images[timing_id] {
+                        ui.same_line();
+
+                        if ui.button(format!("Clear##{}_{}_layer_timing_clear", id, timing_id)) {
+                            image.destroy();
+                            layer.timing_images[timing_id] = None;
+                            data.requires_update = true;
+                        } else {
+                            ui.same_line();
+                            ui.text(image.texture_path.as_str());
+                        }
+                    } else {
+                        ui.same_line();
+                        ui.text("(base)");
+                    }
+                }
+
+                ui.unindent_by(4.0);
+                ui.spacing();
+            }
+        }
+
+        if let Some((from, to)) = moved_layer {
+            if to < layers.len() {
+                layers.swap(from, to);
+                data.requires_update = true;
+            }
+        }
+
+        if let Some(id) = removed_layer {
+            layers.remove(id).destroy();
+            data.requires_update = true;
+        }
+
+        if ui.button("Add Layer") {
+            layers.push(AvatarLayer::new(data));
+            data.requires_update = true;
+        }
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+unsafe fn render_parameters_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Parameters", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        let parameters = &mut (*current_expression(data)).parameters;
+        let mut removed_parameter: Option<usize> = None;
+
+        for (id, parameter) in parameters.iter_mut().enumerate() {
+            if ui.collapsing_header(format!("{}##{}_parameter_group", parameter.settings.name, id), TreeNodeFlags::empty()) {
+                ui.indent_by(4.0);
+
+                if ui.button(format!("Remove##{}_parameter_remove", id)) {
+                    removed_parameter = Some(id);
+                }
+
+                let settings = &mut parameter.settings;
+
+                ui.text("Name");
+                ui.same_line();
+                ui.input_text(format!("##{}_parameter_name", id), &mut settings.name)
+                    .build();
+
+                ui.text("Input");
+                if let Some(_combo) = ui.begin_combo(format!("##{}_parameter_input", id), settings.input.name()) {
+                    for input in ParameterInput::ALL {
+                        if ui.selectable(input.name()) {
+                            settings.input = input;
+                        }
+                    }
+                }
+
+                match settings.input {
+                    ParameterInput::Level | ParameterInput::Pitch => {
+                        ui.text("Input Min / Max");
+                        ui.input_float(format!("##{}_parameter_input_min", id), &mut settings.input_min).build();
+                        ui.input_float(format!("##{}_parameter_input_max", id), &mut settings.input_max).build();
+                    }
+                    ParameterInput::Time => {
+                        ui.text("Period (ms)");
+                        ui.slider(format!("##{}_parameter_period", id), 100.0, 20000.0, &mut settings.period);
+                    }
+                    ParameterInput::Plugin => {
+                        ui.text_wrapped("Set by plugins, using this parameter's name.");
+                    }
+                    ParameterInput::HotkeyAxis => {
+                        ui.text("Increase / Decrease Keys");
+                        ui.input_text(format!("##{}_parameter_key_increase", id), &mut settings.key_increase)
+                            .hint("e.g. Up")
+                            .build();
+                        ui.input_text(format!("##{}_parameter_key_decrease", id), &mut settings.key_decrease)
+                            .hint("e.g. Down")
+                            .build();
+
+                        ui.text("Axis Speed (per second)");
+                        ui.slider(format!("##{}_parameter_axis_speed", id), 0.1, 10.0, &mut settings.axis_speed);
+                    }
+                }
+
+                ui.text("Smoothing (ms)");
+                ui.slider(format!("##{}_parameter_smoothing", id), 0.0, 1000.0, &mut settings.smoothing);
+
+                ui.text("Curve");
+                let preview: Vec<f32> = (0..64).map(|i| settings.evaluate_curve((i as f32) / 63.0)).collect();
+                ui.plot_lines(format!("##{}_parameter_curve_preview", id), &preview)
+                    .graph_size([0.0, 48.0])
+                    .scale_min(0.0)
+                    .scale_max(1.0)
+                    .build();
+
+                let mut removed_point: Option<usize> = None;
+                for (point_id, point) in settings.curve.iter_mut().enumerate() {
+                    ui.input_float2(format!("##{}_{}_parameter_curve_point", id, point_id), point).build();
+                    ui.same_line();
+
+                    if ui.button(format!("X##{}_{}_parameter_curve_remove", id, point_id)) {
+                        removed_point = Some(point_id);
+                    }
+                }
+
+                if let Some(point_id) = removed_point {
+                    settings.curve.remove(point_id);
+                }
+
+                if ui.button(format!("Add Point##{}_parameter_curve_add", id)) {
+                    settings.curve.push([1.0, 1.0]);
+                }
+
+                ui.text("Output");
+                if let Some(_combo) = ui.begin_combo(format!("##{}_parameter_output", id), settings.output.name()) {
+                    for output in ParameterOutput::ALL {
+                        if ui.selectable(output.name()) {
+                            settings.output = output;
+                        }
+                    }
+                }
+
+                ui.text("Target Layer (empty for the whole avatar)");
+                ui.input_text(format!("##{}_parameter_target", id), &mut settings.target_layer)
+                    .build();
+
+                ui.text("Output Min / Max");
+                ui.input_float(format!("##{}_parameter_output_min", id), &mut settings.output_min).build();
+                ui.input_float(format!("##{}_parameter_output_max", id), &mut settings.output_max).build();
+
+                if settings.output == ParameterOutput::Crossfade {
+                    ui.text("Crossfade Image");
+                    ui.text(settings.crossfade_path.as_str());
+                    ui.same_line();
+
+                    if ui.button(format!("Open Path##{}_parameter_crossfade_open_path", id)) {
+                        if let Some(file_path) = pick_image_file() {
+                            settings.crossfade_path = file_path;
+                            parameter.reload_crossfade_image(data);
+                        }
+                    }
+                }
+
+                ui.unindent_by(4.0);
+                ui.spacing();
+            }
+        }
+
+        if let Some(id) = removed_parameter {
+            parameters.remove(id).destroy();
+            data.requires_update = true;
+        }
+
+        if ui.button("Add Parameter") {
+            parameters.push(Parameter::load(data, &ParameterSettings::default()));
+        }
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+/// Returns true if anything about the mesh changed, so the avatar can be redrawn.
+fn render_mesh_ui(ui: &Ui, id: &str, mesh: &mut MeshSettings) -> bool {
+    let mut changed = ui.checkbox(format!("Enabled?##{}_mesh_enabled", id), &mut mesh.enabled);
+
+    if !mesh.enabled {
+        return changed;
+    }
+
+    ui.text("Grid Columns / Rows");
+    changed |= ui.slider(format!("##{}_mesh_columns", id), 1, 64, &mut mesh.columns);
+    changed |= ui.slider(format!("##{}_mesh_rows", id), 1, 64, &mut mesh.rows);
+
+    let mut removed_deformer: Option<usize> = None;
+
+    for (deformer_id, deformer) in mesh.deformers.iter_mut().enumerate() {
+        ui.separator();
+
+        if let Some(_combo) = ui.begin_combo(format!("##{}_{}_mesh_kind", id, deformer_id), deformer.kind.name()) {
+            for kind in DeformKind::ALL {
+                if ui.selectable(kind.name()) {
+                    deformer.kind = kind;
+                    changed = true;
+                }
+            }
+        }
+
+        ui.same_line();
+
+        if ui.button(format!("Remove##{}_{}_mesh_remove", id, deformer_id)) {
+            removed_deformer = Some(deformer_id);
+        }
+
+        ui.text(match deformer.kind {
+            DeformKind::Tilt => "Amount (degrees)",
+            DeformKind::Stretch => "Amount (fraction of the region)",
+            _ => "Amount (fraction of the width)"
+        });
+
+        let range = if deformer.kind == DeformKind::Tilt { 45.0 } else { 0.5 };
+        changed |= ui.slider(format!("##{}_{}_mesh_amount", id, deformer_id), -range, range, &mut deformer.amount);
+
+        ui.text("Driven By Parameter (empty for always)");
+        changed |= ui.input_text(format!("##{}_{}_mesh_parameter", id, deformer_id), &mut deformer.parameter)
+            .build();
+
+        ui.text("Region Center X / Y");
+        changed |= ui.slider(format!("##{}_{}_mesh_center_x", id, deformer_id), 0.0, 1.0, &mut deformer.center_x);
+        changed |= ui.slider(format!("##{}_{}_mesh_center_y", id, deformer_id), 0.0, 1.0, &mut deformer.center_y);
+
+        ui.text("Region Radius X / Y");
+        changed |= ui.slider(format!("##{}_{}_mesh_radius_x", id, deformer_id), 0.0, 2.0, &mut deformer.radius_x);
+        changed |= ui.slider(format!("##{}_{}_mesh_radius_y", id, deformer_id), 0.0, 2.0, &mut deformer.radius_y);
+    }
+
+    if let Some(deformer_id) = removed_deformer {
+        mesh.deformers.remove(deformer_id);
+        changed = true;
+    }
+
+    if ui.button(format!("Add Deformer##{}_mesh_add", id)) {
+        mesh.deformers.push(MeshDeformer::default());
+        changed = true;
+    }
+
+    changed
+}
+
+unsafe fn render_actions_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Actions", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        let actions: *mut Vec<Action<'static>> = &mut data.actions;
+        let mut removed_action: Option<usize> = None;
+        let mut played_action: Option<usize> = None;
+
+        for (id, action) in (*actions).iter_mut().enumerate() {
+            if ui.collapsing_header(format!("{}##{}_action_group", action.settings.name, id), TreeNodeFlags::empty()) {
+                ui.indent_by(4.0);
+
+                if ui.button(format!("Play##{}_action_play", id)) {
+                    played_action = Some(id);
+                }
+
+                ui.same_line();
+
+                if ui.button(format!("Remove##{}_action_remove", id)) {
+                    removed_action = Some(id);
+                }
+
+                let settings = &mut action.settings;
+
+                ui.text("Name");
+                ui.same_line();
+                ui.input_text(format!("##{}_action_name", id), &mut settings.name)
+                    .build();
+
+                ui.text("Hotkey");
+                ui.same_line();
+                ui.input_text(format!("##{}_action_hotkey", id), &mut settings.hotkey)
+                    .hint("e.g. F5")
+                    .build();
+
+                ui.text("Duration (ms)");
+                ui.slider(format!("##{}_action_duration", id), 0.0, 10000.0, &mut settings.duration);
+
+                ui.text("Image (optional)");
+                ui.text(settings.texture_path.as_str());
+                ui.same_line();
+
+                let mut reload = false;
+
+                if ui.button(format!("Open Path##{}_action_open_path", id)) {
+                    if let Some(file_path) = pick_image_file() {
+                        settings.texture_path = file_path;
+                        reload = true;
+                    }
+                }
+
+                if !settings.texture_path.is_empty() {
+                    ui.same_line();
+
+                    if ui.button(format!("Clear##{}_action_clear", id)) {
+                        settings.texture_path = String::new();
+                        reload = true;
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Animation##{}_action_animation", id)) {
+                    let animation = &mut settings.animation;
+
+                    ui.checkbox(format!("Loop?##{}_action_animation_loop", id), &mut animation.looping);
+
+                    ui.text("Sprite Sheet Columns / Rows");
+                    ui.slider(format!("##{}_action_sheet_columns", id), 1, 32, &mut animation.sheet_columns);
+                    ui.slider(format!("##{}_action_sheet_rows", id), 1, 32, &mut animation.sheet_rows);
+
+                    if animation.is_sprite_sheet() {
+                        ui.text("Sprite Sheet Frames (0 = all)");
+                        ui.slider(format!("##{}_action_sheet_frames", id), 0, 1024, &mut animation.sheet_frames);
+
+                        ui.text("Sprite Sheet FPS");
+                        ui.slider(format!("##{}_action_sheet_fps", id), 0.0, 60.0, &mut animation.sheet_fps);
+                    }
+
+                    if ui.button(format!("Reload Image##{}_action_reload", id)) {
+                        reload = true;
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Bounce##{}_action_bounce", id)) {
+                    let bounce = &mut settings.bounce;
+
+                    ui.text("Bounce Height (px) / Duration (ms)");
+                    ui.slider(format!("##{}_action_bounce_height", id), -256.0, 256.0, &mut bounce.height);
+                    ui.slider(format!("##{}_action_bounce_duration", id), 0.0, 2000.0, &mut bounce.duration);
+
+                    ui.text("Bounce Curve");
+                    if let Some(_combo) = ui.begin_combo(format!("##{}_action_bounce_curve", id), bounce.curve.name()) {
+                        for curve in BounceCurve::ALL {
+                            if ui.selectable(curve.name()) {
+                                bounce.curve = curve;
+                            }
+                        }
+                    }
+                }
+
+                if reload {
+                    action.reload_image(data);
+                    data.requires_update = true;
+                }
+
+                ui.unindent_by(4.0);
+                ui.spacing();
+            }
+        }
+
+        if let Some(id) = removed_action {
+            // Indices shift around, so don't let the playing one point at the wrong action.
+            data.action_state = ActionState::new();
+            (*actions).remove(id).destroy();
+            data.requires_update = true;
+        }
+
+        if let Some(id) = played_action {
+            start_action(data, id);
+        }
+
+        if ui.button("Add Action") {
+            let action = Action::load(data, &ActionSettings::default());
+            (*actions).push(action);
+        }
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+unsafe fn render_sounds_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Sound Effects", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        let sounds: *mut Vec<SoundEffect> = &mut data.sounds;
+        let mut removed_sound: Option<usize> = None;
+        let mut played_sound: Option<usize> = None;
+
+        for (id, sound) in (*sounds).iter_mut().enumerate() {
+            if ui.collapsing_header(format!("{}##{}_sound_group", sound.settings.name, id), TreeNodeFlags::empty()) {
+                ui.indent_by(4.0);
+
+                if ui.button(format!("Preview##{}_sound_preview", id)) {
+                    played_sound = Some(id);
+                }
+
+                ui.same_line();
+
+                if ui.button(format!("Remove##{}_sound_remove", id)) {
+                    removed_sound = Some(id);
+                }
+
+                let settings = &mut sound.settings;
+
+                ui.text("Name");
+                ui.same_line();
+                ui.input_text(format!("##{}_sound_name", id), &mut settings.name)
+                    .build();
+
+                ui.text("Sound File");
+                ui.text(settings.sound_path.as_str());
+                ui.same_line();
+
+                if ui.button(format!("Open Path##{}_sound_open_path", id)) {
+                    let file = FileDialog::new()
+                        .add_filter("Sound files", &["wav", "ogg"])
+                        .set_title("Select Sound File")
+                        .pick_file();
+
+                    if let Some(path) = file {
+                        settings.sound_path = String::from(path.to_str().unwrap());
+                        sound.reload_sound();
+                    }
+                }
+
+                let settings = &mut sound.settings;
+
+                ui.text("Volume");
+                ui.slider(format!("##{}_sound_volume", id), 0.0, 2.0, &mut settings.volume);
+
+                ui.text("Expression (empty for none)");
+                ui.input_text(format!("##{}_sound_expression", id), &mut settings.expression)
+                    .build();
+
+                ui.text("Action (empty for none)");
+                ui.input_text(format!("##{}_sound_action", id), &mut settings.action)
+                    .build();
+
+                ui.checkbox(format!("Open Mouth With It?##{}_sound_affects_level", id), &mut settings.affects_level);
+
+                ui.unindent_by(4.0);
+                ui.spacing();
+            }
+        }
+
+        if let Some(id) = removed_sound {
+            (*sounds).remove(id);
+        }
+
+        if let Some(id) = played_sound {
+            play_sound(data, id);
+        }
+
+        if ui.button("Add Sound Effect") {
+            (*sounds).push(SoundEffect::load(&SoundEffectSettings::default()));
+        }
+
+        ui.text_wrapped("Plays on the output device chosen above.");
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+unsafe fn render_scripts_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Scripts", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        let mut removed_script: Option<usize> = None;
+        let mut reloaded_script: Option<usize> = None;
+
+        for (id, path) in data.scripts.iter().enumerate() {
+            ui.text(path.as_str());
+            ui.same_line();
+
+            if ui.button(format!("Reload##{}_script_reload", id)) {
+                reloaded_script = Some(id);
+            }
+
+            ui.same_line();
+
+            if ui.button(format!("Remove##{}_script_remove", id)) {
+                removed_script = Some(id);
+            }
+
+            if let Some(error) = data.script_state.scripts.get(id).and_then(|script| script.error.as_ref()) {
+                ui.text_colored([1.0, 0.4, 0.4, 1.0], error.as_str());
+            }
+        }
+
+        if let Some(id) = removed_script {
+            data.scripts.remove(id);
+        }
+
+        if let Some(id) = reloaded_script {
+            reload_script(data, id);
+        }
+
+        if ui.button("Add Script") {
+            let file = FileDialog::new()
+                .add_filter("Rhai scripts", &["rhai"])
+                .set_title("Select Script")
+                .pick_file();
+
+            if let Some(path) = file {
+                data.scripts.push(String::from(path.to_str().unwrap()));
+            }
+        }
+
+        ui.text_wrapped("Scripts can define on_tick(level, dt), on_state_change(expression, timing) and on_hotkey(key), and are reloaded whenever they change.");
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+unsafe fn render_plugins_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Plugins", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        ui.text("Plugin Directory (applies after a restart)");
+        ui.text(data.plugin.directory.as_str());
+        ui.same_line();
+
+        if ui.button("Open Path##plugin_directory_open_path") {
+            let folder = FileDialog::new()
+                .set_title("Select Plugin Directory")
+                .pick_folder();
+
+            if let Some(path) = folder {
+                data.plugin.directory = String::from(path.to_str().unwrap());
+            }
+        }
+
+        if !data.plugin.directory.is_empty() {
+            ui.same_line();
+
+            if ui.button("Clear##plugin_directory_clear") {
+                data.plugin.directory = String::new();
+            }
+        }
+
+        // Clamped first, a bigger budget from the save file would wrap around otherwise.
+        let mut fuel = data.plugin.fuel_per_frame.min(i32::MAX as u64) as i32;
+        ui.text("Fuel Per Frame");
+        if ui.input_int("##plugin_fuel", &mut fuel).build() {
+            data.plugin.fuel_per_frame = fuel.max(1) as u64;
+        }
+
+        for plugin in data.plugin_state.plugins.iter() {
+            ui.bullet_text(plugin.name.as_str());
+
+            if let Some(error) = &plugin.error {
+                ui.text_colored([1.0, 0.4, 0.4, 1.0], error.as_str());
+            }
+        }
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+unsafe fn render_particles_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Particles", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        let emitters: *mut Vec<Emitter<'static>> = &mut data.emitters;
+        let mut removed_emitter: Option<usize> = None;
+
+        for (id, emitter) in (*emitters).iter_mut().enumerate() {
+            if ui.collapsing_header(format!("{}##{}_emitter_group", emitter.settings.name, id), TreeNodeFlags::empty()) {
+                ui.indent_by(4.0);
+
+                if ui.button(format!("Remove##{}_emitter_remove", id)) {
+                    removed_emitter = Some(id);
+                }
+
+                let settings = &mut emitter.settings;
+
+                ui.text("Name");
+                ui.same_line();
+                ui.input_text(format!("##{}_emitter_name", id), &mut settings.name)
+                    .build();
+
+                ui.text("Particle Image");
+                ui.text(settings.texture_path.as_str());
+                ui.same_line();
+
+                if ui.button(format!("Open Path##{}_emitter_open_path", id)) {
+                    if let Some(file_path) = pick_image_file() {
+                        settings.texture_path = file_path;
+                        emitter.reload_image(data);
+                    }
+                }
+
+                let settings = &mut emitter.settings;
+
+                if let Some(_node) = ui.tree_node(format!("Triggers##{}_emitter_triggers", id)) {
+                    ui.text("While Expression Is Active (empty for never)");
+                    ui.input_text(format!("##{}_emitter_expression", id), &mut settings.expression)
+                        .build();
+
+                    ui.text("Particles Per Second");
+                    ui.slider(format!("##{}_emitter_rate", id), 0.0, 60.0, &mut settings.rate);
+
+                    ui.checkbox(format!("Burst On Loud Peaks?##{}_emitter_on_peak", id), &mut settings.on_peak);
+
+                    if settings.on_peak {
+                        ui.text("Peak Level (dB)");
+                        ui.slider(format!("##{}_emitter_peak_level", id), -60.0, 0.0, &mut settings.peak_level);
+                    }
+
+                    ui.text("Burst When Action Starts (empty for never)");
+                    ui.input_text(format!("##{}_emitter_action", id), &mut settings.action)
+                        .build();
+
+                    ui.text("Burst Size");
+                    ui.slider(format!("##{}_emitter_burst", id), 1, 128, &mut settings.burst);
+
+                    if !settings.action.is_empty() && ui.button(format!("Preview Burst##{}_emitter_preview", id)) {
+                        data.pending_bursts.push(settings.action.clone());
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Spawning##{}_emitter_spawning", id)) {
+                    ui.text("Position X / Y");
+                    ui.slider(format!("##{}_emitter_x", id), 0.0, 1.0, &mut settings.x);
+                    ui.slider(format!("##{}_emitter_y", id), 0.0, 1.0, &mut settings.y);
+
+                    ui.text("Spread (px)");
+                    ui.slider(format!("##{}_emitter_spread", id), 0.0, 256.0, &mut settings.spread);
+
+                    ui.checkbox(format!("Follow Avatar?##{}_emitter_follows", id), &mut settings.follows_avatar);
+
+                    ui.text("Direction / Spread (degrees)");
+                    ui.slider(format!("##{}_emitter_direction", id), -180.0, 180.0, &mut settings.direction);
+                    ui.slider(format!("##{}_emitter_direction_spread", id), 0.0, 360.0, &mut settings.direction_spread);
+
+                    ui.text("Speed Min / Max (px/s)");
+                    ui.slider(format!("##{}_emitter_speed_min", id), 0.0, 1000.0, &mut settings.speed_min);
+                    ui.slider(format!("##{}_emitter_speed_max", id), 0.0, 1000.0, &mut settings.speed_max);
+                }
+
+                if let Some(_node) = ui.tree_node(format!("Motion##{}_emitter_motion", id)) {
+                    ui.text("Gravity (px/s²)");
+                    ui.slider(format!("##{}_emitter_gravity", id), -1000.0, 1000.0, &mut settings.gravity);
+
+                    ui.text("Lifetime (ms)");
+                    ui.slider(format!("##{}_emitter_lifetime", id), 100.0, 10000.0, &mut settings.lifetime);
+
+                    ui.checkbox(format!("Fade Out?##{}_emitter_fade", id), &mut settings.fade);
+
+                    ui.text("Spin (degrees/s)");
+                    ui.slider(format!("##{}_emitter_spin", id), 0.0, 720.0, &mut settings.spin);
+
+                    ui.text("Size (px)");
+                    ui.slider(format!("##{}_emitter_size", id), 1.0, 256.0, &mut settings.size);
+                }
+
+                ui.unindent_by(4.0);
+                ui.spacing();
+            }
+        }
+
+        if let Some(id) = removed_emitter {
+            // Particles point at their emitter by index, so they'd end up drawn with the wrong one.
+            data.particles.clear();
+            (*emitters).remove(id).image.destroy();
+            data.requires_update = true;
+        }
+
+        if ui.button("Add Emitter") {
+            let emitter = Emitter::load(data, &EmitterSettings::default());
+            (*emitters).push(emitter);
+        }
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+unsafe fn render_captions_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Captions", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        ui.checkbox("Enabled?##caption_enabled", &mut data.caption.enabled);
+
+        if data.caption.enabled {
+            ui.input_text_multiline("##caption_input", &mut data.caption_input, [0.0, 48.0])
+                .build();
+
+            if ui.button("Show##caption_show") {
+                let text = data.caption_input.clone();
+                show_caption(data, text.as_str());
+            }
+
+            ui.same_line();
+
+            if ui.button("Hide##caption_hide") {
+                hide_caption(data);
+            }
+
+            ui.text("Watched Text File");
+            ui.text(data.caption.watch_path.as_str());
+            ui.same_line();
+
+            if ui.button("Open Path##caption_watch_open_path") {
+                let file = FileDialog::new()
+                    .add_filter("Text files", &["txt"])
+                    .set_title("Select Text File")
+                    .pick_file();
+
+                if let Some(path) = file {
+                    data.caption.watch_path = String::from(path.to_str().unwrap());
+                }
+            }
+
+            if !data.caption.watch_path.is_empty() {
+                ui.same_line();
+
+                if ui.button("Clear##caption_watch_clear") {
+                    data.caption.watch_path = String::new();
+                }
+            }
+
+            let settings = &mut data.caption;
+
+            ui.text("Font Size (applies after a restart)");
+            let mut font_size = settings.font_size as i32;
+            if ui.slider("##caption_font_size", 8, 72, &mut font_size) {
+                settings.font_size = font_size as u16;
+            }
+
+            ui.text("Text / Bubble Color");
+            if ui.color_edit3("##caption_text_color", &mut settings.text_color) {
+                data.caption_state.is_dirty = true;
+            }
+
+            ui.color_edit4("##caption_bubble_color", &mut settings.bubble_color);
+
+            ui.text("Padding / Max Width (px)");
+            ui.slider("##caption_padding", 0, 64, &mut settings.padding);
+            if ui.slider("##caption_max_width", 64, 512, &mut settings.max_width) {
+                data.caption_state.is_dirty = true;
+            }
+
+            ui.text("Position X / Y");
+            ui.slider("##caption_x", 0.0, 1.0, &mut settings.x);
+            ui.slider("##caption_y", 0.0, 1.0, &mut settings.y);
+
+            ui.text("Fade In / Out (ms)");
+            ui.slider("##caption_fade_in", 0.0, 2000.0, &mut settings.fade_in);
+            ui.slider("##caption_fade_out", 0.0, 2000.0, &mut settings.fade_out);
+
+            ui.text("Duration (ms, 0 = until replaced)");
+            ui.slider("##caption_duration", 0.0, 30000.0, &mut settings.duration);
+        }
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+unsafe fn render_lipsync_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Text Lip Sync", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        ui.input_text_multiline("##lipsync_input", &mut data.lipsync_input, [0.0, 48.0])
+            .build();
+
+        if ui.button("Speak##lipsync_speak") {
+            let text = data.lipsync_input.clone();
+            speak_text(data, text.as_str());
+        }
+
+        ui.same_line();
+
+        if ui.button("Stop##lipsync_stop") {
+            stop_speaking(data);
+        }
+
+        let settings = &mut data.lipsync;
+
+        ui.text("Speaking Rate (syllables/s)");
+        ui.slider("##lipsync_rate", 1.0, 15.0, &mut settings.rate);
+
+        ui.text("Mouth Open Fraction");
+        ui.slider("##lipsync_open_fraction", 0.05, 1.0, &mut settings.open_fraction);
+
+        ui.text("Wide / Narrow Vowel Level (dB)");
+        ui.slider("##lipsync_open_level", -60.0, 0.0, &mut settings.open_level);
+        ui.slider("##lipsync_narrow_level", -60.0, 0.0, &mut settings.narrow_level);
+
+        ui.text("Word / Comma / Sentence Pause (syllables)");
+        ui.slider("##lipsync_word_gap", 0.0, 2.0, &mut settings.word_gap);
+        ui.slider("##lipsync_comma_pause", 0.0, 4.0, &mut settings.comma_pause);
+        ui.slider("##lipsync_sentence_pause", 0.0, 6.0, &mut settings.sentence_pause);
+
+        ui.checkbox("Show As Caption?##lipsync_show_caption", &mut settings.show_caption);
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
+
+unsafe fn render_tts_ui(ui: &Ui, data: &mut SharedData) {
+    let group = ui.begin_group();
+
+    if ui.collapsing_header("Text To Speech", TreeNodeFlags::empty()) {
+        ui.indent_by(4.0);
+
+        let settings = &mut data.tts;
+
+        ui.checkbox("Speak Lip Synced Text Aloud?##tts_enabled", &mut settings.enabled);
+
+        ui.text("Engine");
+        if let Some(_combo) = ui.begin_combo("##tts_engine", settings.engine.name()) {
+            for engine in TtsEngine::ALL {
+                if ui.selectable(engine.name()) {
+                    settings.engine = engine;
+                }
+            }
+        }
+
+        ui.text("Engine Binary (empty to use the PATH)");
+        ui.input_text("##tts_binary_path", &mut settings.binary_path)
+            .build();
+
+        if settings.engine == TtsEngine::Piper {
+            ui.text("Voice Model");
+            ui.text(settings.voice.as_str());
+            ui.same_line();
+
+            if ui.button("Open Path##tts_voice_open_path") {
+                let file = FileDialog::new()
+                    .add_filter("Piper voice models", &["onnx"])
+                    .set_title("Select Voice Model")
+                    .pick_file();
+
+                if let Some(path) = file {
+                    settings.voice = String::from(path.to_str().unwrap());
+                }
+            }
+        } else {
+            ui.text("Voice");
+            ui.input_text("##tts_voice", &mut settings.voice)
+                .hint("e.g. en-us")
+                .build();
+        }
+
+        ui.text("Speed / Volume");
+        ui.slider("##tts_speed", 0.25, 3.0, &mut settings.speed);
+        ui.slider("##tts_volume", 0.0, 2.0, &mut settings.volume);
+
+        ui.text_wrapped("Plays on the output device chosen above, text is entered under Text Lip Sync.");
+
+        ui.unindent_by(4.0);
+    }
+
+    group.end();
+}
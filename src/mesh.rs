@@ -0,0 +1,234 @@
+use std::f32::consts::PI;
+use std::os::raw::c_int;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::surface::Surface;
+use sdl2_sys::{SDL_Color, SDL_FPoint, SDL_RenderGeometry, SDL_SetTextureAlphaMod, SDL_SetTextureColorMod, SDL_Vertex};
+use serde::{Deserialize, Serialize};
+use crate::SharedData;
+use crate::parameters::parameter_value;
+use crate::transform::{AvatarTransform, copy_transformed};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DeformKind {
+    Bend,
+    Tilt,
+    Stretch,
+    Turn,
+}
+
+impl DeformKind {
+    pub const ALL: [DeformKind; 4] = [DeformKind::Bend, DeformKind::Tilt, DeformKind::Stretch, DeformKind::Turn];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeformKind::Bend => "Bend",
+            DeformKind::Tilt => "Tilt",
+            DeformKind::Stretch => "Stretch",
+            DeformKind::Turn => "Turn (Parallax)",
+        }
+    }
+}
+
+/// A single warp applied to part of the mesh, fading out towards the edges of its region.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MeshDeformer {
+    pub(crate) kind: DeformKind,
+    // Bend and Turn are a fraction of the image's width, Tilt is in degrees, Stretch is a fraction of the region's size
+    pub(crate) amount: f32,
+    // Name of a parameter in the current expression that scales the amount, or empty to always apply it
+    pub(crate) parameter: String,
+    // Fractions of the image, 0.5 x 0.5 being the middle
+    pub(crate) center_x: f32,
+    pub(crate) center_y: f32,
+    pub(crate) radius_x: f32,
+    pub(crate) radius_y: f32,
+}
+
+impl Default for MeshDeformer {
+    fn default() -> Self {
+        MeshDeformer {
+            kind: DeformKind::Bend,
+            amount: 0.05,
+            parameter: String::new(),
+            center_x: 0.5,
+            center_y: 0.5,
+            radius_x: 0.75,
+            radius_y: 0.75
+        }
+    }
+}
+
+impl MeshDeformer {
+    /// How much of the deformation a point (in fractions of the image) gets, 1 in the
+    /// middle of the region, easing down to 0 at its edge.
+    fn weight(&self, u: f32, v: f32) -> f32 {
+        if self.radius_x <= 0.0 || self.radius_y <= 0.0 {
+            return 0.0;
+        }
+
+        let dx = (u - self.center_x) / self.radius_x;
+        let dy = (v - self.center_y) / self.radius_y;
+        let falloff = (1.0 - (dx * dx + dy * dy).sqrt()).clamp(0.0, 1.0);
+
+        falloff * falloff * (3.0 - 2.0 * falloff)
+    }
+
+    /// Moves a point within the destination rect, with (u, v) being where it started in fractions of the image.
+    fn apply(&self, amount: f32, dest: Rect, u: f32, v: f32, point: (f32, f32)) -> (f32, f32) {
+        let weight = self.weight(u, v) * amount;
+        if weight == 0.0 {
+            return point;
+        }
+
+        let width = dest.width() as f32;
+        let height = dest.height() as f32;
+        let center = ((dest.x() as f32) + self.center_x * width, (dest.y() as f32) + self.center_y * height);
+
+        match self.kind {
+            // The bottom stays put, and the top of the region moves the furthest.
+            DeformKind::Bend => {
+                let lift = (1.0 - v).clamp(0.0, 1.0);
+                (point.0 + weight * width * lift * lift, point.1)
+            }
+            DeformKind::Tilt => {
+                let angle = weight * PI / 180.0;
+                let (dx, dy) = (point.0 - center.0, point.1 - center.1);
+
+                (center.0 + dx * angle.cos() - dy * angle.sin(), center.1 + dx * angle.sin() + dy * angle.cos())
+            }
+            DeformKind::Stretch => {
+                let scale = 1.0 + weight;
+                (center.0 + (point.0 - center.0) * scale, center.1 + (point.1 - center.1) * scale)
+            }
+            // The middle moves while the sides stay pinned, so the near side looks like it's turning towards you.
+            DeformKind::Turn => {
+                let depth = 1.0 - (2.0 * u - 1.0).abs();
+                (point.0 + weight * width * depth, point.1)
+            }
+        }
+    }
+}
+
+/// A grid laid over an image, so it can be bent and stretched instead of only moved around.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MeshSettings {
+    pub(crate) enabled: bool,
+    pub(crate) columns: u32,
+    pub(crate) rows: u32,
+    pub(crate) deformers: Vec<MeshDeformer>,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        MeshSettings {
+            enabled: false,
+            columns: 8,
+            rows: 8,
+            deformers: Vec::new()
+        }
+    }
+}
+
+impl MeshSettings {
+    pub fn is_active(&self) -> bool {
+        self.enabled && !self.deformers.is_empty()
+    }
+}
+
+fn transform_point(transform: &AvatarTransform, pivot: (f64, f64), point: (f32, f32)) -> SDL_FPoint {
+    let scale_x = transform.scale_x.max(0.01);
+    let scale_y = transform.scale_y.max(0.01);
+
+    let x = pivot.0 + ((point.0 as f64) - pivot.0) * scale_x;
+    let y = pivot.1 + ((point.1 as f64) - pivot.1) * scale_y;
+
+    // Rotated around the moved pivot, same as copy_transformed does.
+    let (sin, cos) = transform.rotation.to_radians().sin_cos();
+    let (dx, dy) = (x - pivot.0, y - pivot.1);
+
+    SDL_FPoint {
+        x: (pivot.0 + dx * cos - dy * sin + transform.offset_x) as f32,
+        y: (pivot.1 + dx * sin + dy * cos + transform.offset_y) as f32
+    }
+}
+
+unsafe fn copy_mesh(canvas: *mut Canvas<Surface<'static>>, texture: &Texture, source: Option<Rect>, dest: Rect, mesh: &MeshSettings, data: &SharedData, transform: &AvatarTransform, pivot: (f64, f64), color: SDL_Color) {
+    let query = texture.query();
+    let source = source.unwrap_or(Rect::new(0, 0, query.width, query.height));
+
+    let columns = mesh.columns.clamp(1, 64);
+    let rows = mesh.rows.clamp(1, 64);
+
+    let amounts: Vec<f32> = mesh.deformers.iter()
+        .map(|deformer| {
+            if deformer.parameter.is_empty() {
+                deformer.amount
+            } else {
+                deformer.amount * parameter_value(data, deformer.parameter.as_str()).unwrap_or(0.0)
+            }
+        })
+        .collect();
+
+    let mut vertices: Vec<SDL_Vertex> = Vec::with_capacity(((columns + 1) * (rows + 1)) as usize);
+
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let u = (column as f32) / (columns as f32);
+            let v = (row as f32) / (rows as f32);
+
+            let mut point = ((dest.x() as f32) + u * (dest.width() as f32), (dest.y() as f32) + v * (dest.height() as f32));
+            for (deformer, amount) in mesh.deformers.iter().zip(amounts.iter()) {
+                point = deformer.apply(*amount, dest, u, v, point);
+            }
+
+            vertices.push(SDL_Vertex {
+                position: transform_point(transform, pivot, point),
+                color,
+                tex_coord: SDL_FPoint {
+                    x: ((source.x() as f32) + u * (source.width() as f32)) / (query.width as f32),
+                    y: ((source.y() as f32) + v * (source.height() as f32)) / (query.height as f32)
+                }
+            });
+        }
+    }
+
+    let mut indices: Vec<c_int> = Vec::with_capacity((columns * rows * 6) as usize);
+    let stride = (columns + 1) as c_int;
+
+    for row in 0..(rows as c_int) {
+        for column in 0..(columns as c_int) {
+            let top_left = row * stride + column;
+            let bottom_left = top_left + stride;
+
+            indices.extend_from_slice(&[top_left, top_left + 1, bottom_left, top_left + 1, bottom_left + 1, bottom_left]);
+        }
+    }
+
+    SDL_RenderGeometry((*canvas).raw(), texture.raw(), vertices.as_ptr(), vertices.len() as c_int, indices.as_ptr(), indices.len() as c_int);
+}
+
+/// Draws an image through its mesh if it has one, or as a plain transformed copy otherwise.
+pub unsafe fn copy_warped(canvas: *mut Canvas<Surface<'static>>, texture: &Texture, source: Option<Rect>, dest: Rect, mesh: &MeshSettings, data: &SharedData, transform: &AvatarTransform, pivot: (f64, f64), alpha: f32, color: (u8, u8, u8)) {
+    if alpha <= 0.0 {
+        return;
+    }
+
+    let alpha = (alpha.min(1.0) * 255.0) as u8;
+
+    // Vertex colours already tint the geometry, so the texture's own modulation is left alone.
+    if mesh.is_active() {
+        copy_mesh(canvas, texture, source, dest, mesh, data, transform, pivot, SDL_Color { r: color.0, g: color.1, b: color.2, a: alpha });
+        return;
+    }
+
+    SDL_SetTextureAlphaMod(texture.raw(), alpha);
+    SDL_SetTextureColorMod(texture.raw(), color.0, color.1, color.2);
+
+    copy_transformed(canvas, texture, source, dest, transform, pivot);
+
+    SDL_SetTextureAlphaMod(texture.raw(), 255);
+    SDL_SetTextureColorMod(texture.raw(), 255, 255, 255);
+}
@@ -0,0 +1,313 @@
+use sdl2::keyboard::Keycode;
+use serde::{Deserialize, Serialize};
+use crate::{current_expression, current_level, NANOS_TO_MILLIS, SharedData};
+use crate::layers::LayerImage;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ParameterInput {
+    Level,
+    Pitch,
+    Time,
+    HotkeyAxis,
+    Plugin,
+}
+
+impl ParameterInput {
+    pub const ALL: [ParameterInput; 5] = [ParameterInput::Level, ParameterInput::Pitch, ParameterInput::Time, ParameterInput::HotkeyAxis, ParameterInput::Plugin];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParameterInput::Level => "Level (dB)",
+            ParameterInput::Pitch => "Pitch (Hz)",
+            ParameterInput::Time => "Time",
+            ParameterInput::HotkeyAxis => "Hotkey Axis",
+            ParameterInput::Plugin => "Plugin",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ParameterOutput {
+    OffsetX,
+    OffsetY,
+    Scale,
+    Rotation,
+    Opacity,
+    Crossfade,
+}
+
+impl ParameterOutput {
+    pub const ALL: [ParameterOutput; 6] = [ParameterOutput::OffsetX, ParameterOutput::OffsetY, ParameterOutput::Scale, ParameterOutput::Rotation, ParameterOutput::Opacity, ParameterOutput::Crossfade];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParameterOutput::OffsetX => "Offset X",
+            ParameterOutput::OffsetY => "Offset Y",
+            ParameterOutput::Scale => "Scale",
+            ParameterOutput::Rotation => "Rotation",
+            ParameterOutput::Opacity => "Opacity",
+            ParameterOutput::Crossfade => "Crossfade",
+        }
+    }
+}
+
+/// Maps an input through a curve onto one output of a layer, or the whole avatar.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ParameterSettings {
+    pub(crate) name: String,
+    pub(crate) input: ParameterInput,
+    // Level and pitch inputs get mapped from this range onto 0 to 1
+    pub(crate) input_min: f32,
+    pub(crate) input_max: f32,
+    // How long the time input takes to go from 0 to 1, in milliseconds
+    pub(crate) period: f32,
+    // SDL key names, held to move the hotkey axis up or down
+    pub(crate) key_increase: String,
+    pub(crate) key_decrease: String,
+    // How much the hotkey axis moves per second
+    pub(crate) axis_speed: f32,
+    // in milliseconds, 0 follows the input straight away
+    pub(crate) smoothing: f32,
+    // Points going from 0 to 1 on both axes, linearly interpolated
+    pub(crate) curve: Vec<[f32; 2]>,
+    pub(crate) output: ParameterOutput,
+    // Layer name, or empty for the whole avatar
+    pub(crate) target_layer: String,
+    pub(crate) output_min: f32,
+    pub(crate) output_max: f32,
+    // The image crossfaded to when the output is Crossfade
+    pub(crate) crossfade_path: String,
+}
+
+impl Default for ParameterSettings {
+    fn default() -> Self {
+        ParameterSettings {
+            name: String::from("Parameter"),
+            input: ParameterInput::Level,
+            input_min: -30.0,
+            input_max: 0.0,
+            period: 2000.0,
+            key_increase: String::new(),
+            key_decrease: String::new(),
+            axis_speed: 2.0,
+            smoothing: 80.0,
+            curve: vec![[0.0, 0.0], [1.0, 1.0]],
+            output: ParameterOutput::OffsetY,
+            target_layer: String::new(),
+            output_min: 0.0,
+            output_max: -16.0,
+            crossfade_path: String::new()
+        }
+    }
+}
+
+impl ParameterSettings {
+    pub fn evaluate_curve(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+
+        if self.curve.is_empty() {
+            return x;
+        }
+
+        let mut points = self.curve.clone();
+        points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+
+        if x <= points[0][0] {
+            return points[0][1];
+        }
+
+        for pair in points.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+
+            if x <= to[0] {
+                if to[0] - from[0] <= 0.0 {
+                    return to[1];
+                }
+
+                return from[1] + (to[1] - from[1]) * (x - from[0]) / (to[0] - from[0]);
+            }
+        }
+
+        points[points.len() - 1][1]
+    }
+}
+
+pub struct Parameter<'a> {
+    pub(crate) settings: ParameterSettings,
+    // After the curve and smoothing, from 0 to 1
+    pub(crate) value: f32,
+    axis: f32,
+    pub(crate) crossfade_image: Option<LayerImage<'a>>,
+}
+
+impl Parameter<'_> {
+    pub fn load(data: &SharedData, settings: &ParameterSettings) -> Parameter<'static> {
+        let mut parameter = Parameter {
+            settings: settings.clone(),
+            value: 0.0,
+            axis: 0.0,
+            crossfade_image: None
+        };
+
+        parameter.reload_crossfade_image(data);
+        parameter
+    }
+
+    pub fn reload_crossfade_image(&mut self, data: &SharedData) {
+        if let Some(image) = &self.crossfade_image {
+            unsafe {
+                image.destroy();
+            }
+        }
+
+        self.crossfade_image = if self.settings.crossfade_path.is_empty() {
+            None
+        } else {
+            Some(LayerImage::load(data, self.settings.crossfade_path.as_str()))
+        };
+    }
+
+    pub fn output_value(&self) -> f32 {
+        self.settings.output_min + (self.settings.output_max - self.settings.output_min) * self.value
+    }
+
+    pub unsafe fn destroy(&self) {
+        if let Some(image) = &self.crossfade_image {
+            image.destroy();
+        }
+    }
+}
+
+/// Everything the parameters do to a single layer (or the whole avatar) this frame.
+pub struct ParameterModifiers {
+    pub(crate) offset_x: f64,
+    pub(crate) offset_y: f64,
+    pub(crate) scale: f64,
+    pub(crate) rotation: f64,
+    pub(crate) opacity: f32,
+    pub(crate) crossfade: Option<(f32, *const LayerImage<'static>)>,
+}
+
+pub fn parameter_modifiers(data: &SharedData, target_layer: &str) -> ParameterModifiers {
+    let mut modifiers = ParameterModifiers {
+        offset_x: 0.0,
+        offset_y: 0.0,
+        scale: 1.0,
+        rotation: 0.0,
+        opacity: 1.0,
+        crossfade: None
+    };
+
+    for parameter in unsafe { (*current_expression(data)).parameters.iter() } {
+        if parameter.settings.target_layer != target_layer {
+            continue;
+        }
+
+        let output = parameter.output_value();
+
+        match parameter.settings.output {
+            ParameterOutput::OffsetX => modifiers.offset_x += output as f64,
+            ParameterOutput::OffsetY => modifiers.offset_y += output as f64,
+            ParameterOutput::Scale => modifiers.scale *= output as f64,
+            ParameterOutput::Rotation => modifiers.rotation += output as f64,
+            ParameterOutput::Opacity => modifiers.opacity *= output.clamp(0.0, 1.0),
+            ParameterOutput::Crossfade => {
+                if let Some(image) = &parameter.crossfade_image {
+                    modifiers.crossfade = Some((output.clamp(0.0, 1.0), image));
+                }
+            }
+        }
+    }
+
+    modifiers
+}
+
+/// Gets the current value of a parameter in the current expression by name, from 0 to 1.
+pub fn parameter_value(data: &SharedData, name: &str) -> Option<f32> {
+    unsafe { (*current_expression(data)).parameters.iter() }
+        .find(|parameter| parameter.settings.name == name)
+        .map(|parameter| parameter.value)
+}
+
+fn is_key_held(data: &SharedData, key_name: &str) -> bool {
+    if key_name.is_empty() {
+        return false;
+    }
+
+    match Keycode::from_name(key_name) {
+        Some(keycode) => data.held_keys.contains(&keycode),
+        None => false
+    }
+}
+
+fn normalize(value: f32, min: f32, max: f32) -> f32 {
+    if max == min {
+        return 0.0;
+    }
+
+    ((value - min) / (max - min)).clamp(0.0, 1.0)
+}
+
+/// Updates every parameter in the current expression, and only requests an update
+/// when one of them actually moves.
+pub fn tick_parameters(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+    data.parameter_time += millis as f64;
+
+    let level = current_level(data);
+    let pitch = unsafe { (*data.audio_data).current_pitch };
+    let mut changed = false;
+
+    for parameter in unsafe { (*current_expression(data)).parameters.iter_mut() } {
+        let settings = &parameter.settings;
+
+        let input = match settings.input {
+            ParameterInput::Level => normalize(level, settings.input_min, settings.input_max),
+            ParameterInput::Pitch => normalize(pitch, settings.input_min, settings.input_max),
+            ParameterInput::Time => {
+                if settings.period > 0.0 {
+                    ((data.parameter_time % (settings.period as f64)) / (settings.period as f64)) as f32
+                } else {
+                    0.0
+                }
+            }
+            ParameterInput::HotkeyAxis => {
+                let step = settings.axis_speed * millis / 1000.0;
+
+                if is_key_held(data, settings.key_increase.as_str()) {
+                    parameter.axis += step;
+                }
+
+                if is_key_held(data, settings.key_decrease.as_str()) {
+                    parameter.axis -= step;
+                }
+
+                parameter.axis = parameter.axis.clamp(0.0, 1.0);
+                parameter.axis
+            }
+            ParameterInput::Plugin => data.plugin_state.parameters.get(&settings.name).copied().unwrap_or(0.0).clamp(0.0, 1.0),
+        };
+
+        let target = settings.evaluate_curve(input);
+        let previous = parameter.value;
+
+        if settings.smoothing <= 0.0 {
+            parameter.value = target;
+        } else {
+            parameter.value += (target - parameter.value) * (millis / settings.smoothing).min(1.0);
+        }
+
+        if (parameter.value - target).abs() < 0.0005 {
+            parameter.value = target;
+        }
+
+        if parameter.value != previous {
+            changed = true;
+        }
+    }
+
+    if changed {
+        data.requires_update = true;
+    }
+}
@@ -0,0 +1,254 @@
+use rand::Rng;
+use sdl2::rect::{Point, Rect};
+use sdl2_sys::SDL_SetTextureAlphaMod;
+use serde::{Deserialize, Serialize};
+use crate::{current_expression, current_level, NANOS_TO_MILLIS, SharedData};
+use crate::layers::LayerImage;
+use crate::transform::avatar_transform;
+
+// Keeps a runaway emitter from eating the whole frame.
+const MAX_PARTICLES: usize = 512;
+
+/// Spawns small textured particles (hearts, sweat drops, sparkles, etc.) around the avatar.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmitterSettings {
+    pub(crate) name: String,
+    pub(crate) texture_path: String,
+    // Emits constantly while this expression is active, empty to never do that
+    pub(crate) expression: String,
+    // Particles per second while the expression is active
+    pub(crate) rate: f32,
+    // Bursts when the level goes above peak_level, in dB
+    pub(crate) on_peak: bool,
+    pub(crate) peak_level: f32,
+    // Bursts when this action starts, empty to never do that
+    pub(crate) action: String,
+    // How many particles a burst spawns
+    pub(crate) burst: u32,
+    // Where particles spawn, in fractions of the window
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    // in pixels
+    pub(crate) spread: f32,
+    // Moves the spawn point along with the bounce, physics and so on
+    pub(crate) follows_avatar: bool,
+    // in degrees, 0 being straight up
+    pub(crate) direction: f32,
+    pub(crate) direction_spread: f32,
+    // in pixels per second
+    pub(crate) speed_min: f32,
+    pub(crate) speed_max: f32,
+    // in pixels per second squared, positive pulls down
+    pub(crate) gravity: f32,
+    // in milliseconds
+    pub(crate) lifetime: f32,
+    pub(crate) fade: bool,
+    // in degrees per second, picked randomly between -spin and spin
+    pub(crate) spin: f32,
+    // Width of each particle in pixels, the height keeps the image's aspect ratio
+    pub(crate) size: f32,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        EmitterSettings {
+            name: String::from("Emitter"),
+            texture_path: String::new(),
+            expression: String::new(),
+            rate: 4.0,
+            on_peak: false,
+            peak_level: -10.0,
+            action: String::new(),
+            burst: 8,
+            x: 0.5,
+            y: 0.3,
+            spread: 48.0,
+            follows_avatar: true,
+            direction: 0.0,
+            direction_spread: 45.0,
+            speed_min: 60.0,
+            speed_max: 160.0,
+            gravity: 120.0,
+            lifetime: 1500.0,
+            fade: true,
+            spin: 90.0,
+            size: 24.0
+        }
+    }
+}
+
+pub struct Emitter<'a> {
+    pub(crate) settings: EmitterSettings,
+    pub(crate) image: LayerImage<'a>,
+    // Fractional particles left over from the last frame
+    accumulator: f32,
+    was_loud: bool,
+}
+
+impl Emitter<'_> {
+    pub fn load(data: &SharedData, settings: &EmitterSettings) -> Emitter<'static> {
+        Emitter {
+            settings: settings.clone(),
+            image: LayerImage::load(data, settings.texture_path.as_str()),
+            accumulator: 0.0,
+            was_loud: false
+        }
+    }
+
+    pub fn reload_image(&mut self, data: &SharedData) {
+        unsafe {
+            self.image.destroy();
+        }
+
+        self.image = LayerImage::load(data, self.settings.texture_path.as_str());
+    }
+}
+
+pub struct Particle {
+    emitter: usize,
+    x: f32,
+    y: f32,
+    velocity_x: f32,
+    velocity_y: f32,
+    rotation: f32,
+    spin: f32,
+    age: f32,
+}
+
+fn spawn(data: &mut SharedData, emitter_index: usize, count: u32, window_size: (u32, u32)) {
+    let settings = &data.emitters[emitter_index].settings;
+    let mut rng = rand::thread_rng();
+
+    let (offset_x, offset_y) = if settings.follows_avatar {
+        let transform = avatar_transform(data);
+        (transform.offset_x as f32, transform.offset_y as f32)
+    } else {
+        (0.0, 0.0)
+    };
+
+    let origin_x = (window_size.0 as f32) * settings.x + offset_x;
+    let origin_y = (window_size.1 as f32) * settings.y + offset_y;
+
+    for _ in 0..count {
+        if data.particles.len() >= MAX_PARTICLES {
+            break;
+        }
+
+        let half_spread = settings.direction_spread.abs() / 2.0;
+        let angle = (settings.direction + rng.gen_range(-half_spread..=half_spread)).to_radians();
+        let speed = rng.gen_range(settings.speed_min.min(settings.speed_max)..=settings.speed_max.max(settings.speed_min));
+        let distance = rng.gen_range(0.0..=settings.spread.max(0.0));
+        let placement = rng.gen_range(0.0..std::f32::consts::TAU);
+        let spin = settings.spin.abs();
+
+        data.particles.push(Particle {
+            emitter: emitter_index,
+            x: origin_x + placement.cos() * distance,
+            y: origin_y + placement.sin() * distance,
+            velocity_x: angle.sin() * speed,
+            velocity_y: -angle.cos() * speed,
+            rotation: rng.gen_range(0.0..360.0),
+            spin: rng.gen_range(-spin..=spin),
+            age: 0.0
+        });
+    }
+}
+
+/// Bursts every emitter hooked up to the given action, on the next tick.
+pub fn emit_for_action(data: &mut SharedData, action_name: &str) {
+    data.pending_bursts.push(String::from(action_name));
+    data.requires_update = true;
+}
+
+pub fn tick_particles(data: &mut SharedData, window_size: (u32, u32), nanos_since_last_frame: u128) {
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+    let secs = millis / 1000.0;
+    let level = current_level(data);
+    let expression_name = unsafe { (*current_expression(data)).name.clone() };
+
+    let pending_bursts: Vec<String> = data.pending_bursts.drain(..).collect();
+
+    for i in 0..data.emitters.len() {
+        let emitter = &mut data.emitters[i];
+        let mut count = 0;
+
+        for action_name in pending_bursts.iter() {
+            if !emitter.settings.action.is_empty() && emitter.settings.action.eq_ignore_ascii_case(action_name) {
+                count += emitter.settings.burst;
+            }
+        }
+
+        if !emitter.settings.expression.is_empty() && emitter.settings.expression.eq_ignore_ascii_case(expression_name.as_str()) {
+            emitter.accumulator += emitter.settings.rate.max(0.0) * secs;
+            count += emitter.accumulator.floor() as u32;
+            emitter.accumulator = emitter.accumulator.fract();
+        } else {
+            emitter.accumulator = 0.0;
+        }
+
+        // Only burst on the way up, otherwise shouting would spawn particles every frame.
+        let is_loud = level >= emitter.settings.peak_level;
+        if emitter.settings.on_peak && is_loud && !emitter.was_loud {
+            count += emitter.settings.burst;
+        }
+
+        emitter.was_loud = is_loud;
+
+        if count > 0 {
+            spawn(data, i, count, window_size);
+        }
+    }
+
+    if data.particles.is_empty() {
+        return;
+    }
+
+    let emitters = &data.emitters;
+    data.particles.retain_mut(|particle| {
+        let settings = match emitters.get(particle.emitter) {
+            Some(emitter) => &emitter.settings,
+            None => return false
+        };
+
+        particle.age += millis;
+        particle.velocity_y += settings.gravity * secs;
+        particle.x += particle.velocity_x * secs;
+        particle.y += particle.velocity_y * secs;
+        particle.rotation += particle.spin * secs;
+
+        particle.age < settings.lifetime
+    });
+
+    // Keeps going one frame past the last particle, so it gets cleared off the screen.
+    data.requires_update = true;
+}
+
+pub unsafe fn render_particles(data: &SharedData) {
+    let canvas = data.pngtuber_canvas;
+
+    for particle in data.particles.iter() {
+        let emitter = &data.emitters[particle.emitter];
+        let settings = &emitter.settings;
+        let surface = &emitter.image.texture_surface;
+
+        let width = settings.size.max(1.0);
+        let height = width * (surface.height() as f32) / (surface.width().max(1) as f32);
+        let dest = Rect::new((particle.x - width / 2.0) as i32, (particle.y - height / 2.0) as i32, width as u32, height.max(1.0) as u32);
+
+        let alpha = if settings.fade && settings.lifetime > 0.0 {
+            (1.0 - particle.age / settings.lifetime).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        SDL_SetTextureAlphaMod(emitter.image.texture.raw(), (alpha * 255.0) as u8);
+
+        let center = Point::new((width / 2.0) as i32, (height / 2.0) as i32);
+        (*canvas).copy_ex(&emitter.image.texture, None, Option::from(dest), particle.rotation as f64, Option::from(center), false, false).unwrap();
+    }
+
+    for emitter in data.emitters.iter() {
+        SDL_SetTextureAlphaMod(emitter.image.texture.raw(), 255);
+    }
+}
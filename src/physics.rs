@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use crate::{current_level, NANOS_TO_MILLIS, SharedData, SpeechTiming};
+
+// Big time steps make stiff springs explode, so split them up.
+const MAX_STEP_SECS: f32 = 0.004;
+const PEAK_COOLDOWN_MILLIS: f32 = 150.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SpringSettings {
+    pub(crate) enabled: bool,
+    pub(crate) stiffness: f32,
+    pub(crate) damping: f32,
+    // Kicks given when this timing becomes active, per second
+    pub(crate) offset_impulse: f32,
+    pub(crate) rotation_impulse: f32,
+    pub(crate) scale_impulse: f32,
+    // How far (in dB) the level has to jump above its recent average to count as a peak
+    pub(crate) peak_threshold: f32,
+    // Peaks kick by this much of the impulses for every 10 dB of jump
+    pub(crate) peak_strength: f32,
+}
+
+impl Default for SpringSettings {
+    fn default() -> Self {
+        SpringSettings {
+            enabled: false,
+            stiffness: 250.0,
+            damping: 14.0,
+            offset_impulse: 400.0,
+            rotation_impulse: 60.0,
+            scale_impulse: 0.5,
+            peak_threshold: 6.0,
+            peak_strength: 1.0
+        }
+    }
+}
+
+pub struct Spring {
+    pub(crate) position: f32,
+    pub(crate) velocity: f32,
+}
+
+impl Spring {
+    pub fn new() -> Spring {
+        Spring {
+            position: 0.0,
+            velocity: 0.0
+        }
+    }
+
+    fn step(&mut self, stiffness: f32, damping: f32, dt: f32) {
+        let acceleration = -stiffness * self.position - damping * self.velocity;
+        self.velocity += acceleration * dt;
+        self.position += self.velocity * dt;
+    }
+
+    fn is_moving(&self) -> bool {
+        self.position != 0.0 || self.velocity != 0.0
+    }
+
+    /// Snaps to exactly 0 once the movement is too small to see, so the renderer can sleep.
+    fn settle(&mut self, epsilon: f32) {
+        if self.position.abs() < epsilon && self.velocity.abs() < epsilon {
+            self.position = 0.0;
+            self.velocity = 0.0;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.position = 0.0;
+        self.velocity = 0.0;
+    }
+}
+
+pub struct PhysicsState {
+    pub(crate) offset: Spring,
+    pub(crate) rotation: Spring,
+    pub(crate) scale: Spring,
+    last_timing: Option<*const SpeechTiming<'static>>,
+    average_level: f32,
+    peak_cooldown: f32,
+    // Flips every kick, so the avatar sways both ways
+    kick_direction: f32,
+}
+
+impl PhysicsState {
+    pub fn new() -> PhysicsState {
+        PhysicsState {
+            offset: Spring::new(),
+            rotation: Spring::new(),
+            scale: Spring::new(),
+            last_timing: None,
+            average_level: -100.0,
+            peak_cooldown: 0.0,
+            kick_direction: 1.0
+        }
+    }
+
+    fn kick(&mut self, settings: &SpringSettings, strength: f32) {
+        self.offset.velocity += settings.offset_impulse * strength;
+        self.rotation.velocity += settings.rotation_impulse * strength * self.kick_direction;
+        self.scale.velocity += settings.scale_impulse * strength;
+        self.kick_direction = -self.kick_direction;
+    }
+
+    fn is_moving(&self) -> bool {
+        self.offset.is_moving() || self.rotation.is_moving() || self.scale.is_moving()
+    }
+}
+
+pub fn tick_physics(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let timing = match data.current_timing {
+        Some(timing) => timing,
+        None => return
+    };
+
+    let settings = unsafe { &(*timing).spring };
+    let level = current_level(data).max(-100.0);
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+    let physics = &mut data.physics;
+
+    if !settings.enabled {
+        physics.last_timing = Some(timing);
+        physics.average_level = level;
+
+        if physics.is_moving() {
+            physics.offset.reset();
+            physics.rotation.reset();
+            physics.scale.reset();
+            data.requires_update = true;
+        }
+
+        return;
+    }
+
+    // Speech onset, or any other state change
+    if !physics.last_timing.is_some_and(|last| std::ptr::eq(last, timing)) {
+        physics.last_timing = Some(timing);
+        physics.kick(settings, 1.0);
+    }
+
+    physics.peak_cooldown -= millis;
+
+    let jump = level - physics.average_level;
+    if physics.peak_cooldown <= 0.0 && jump >= settings.peak_threshold {
+        // Capped, otherwise coming out of silence counts as a massive peak.
+        physics.kick(settings, settings.peak_strength * (jump / 10.0).min(1.5));
+        physics.peak_cooldown = PEAK_COOLDOWN_MILLIS;
+    }
+
+    physics.average_level += (level - physics.average_level) * (millis / 250.0).min(1.0);
+
+    if !physics.is_moving() {
+        return;
+    }
+
+    let mut remaining = millis / 1000.0;
+    while remaining > 0.0 {
+        let dt = remaining.min(MAX_STEP_SECS);
+
+        physics.offset.step(settings.stiffness, settings.damping, dt);
+        physics.rotation.step(settings.stiffness, settings.damping, dt);
+        physics.scale.step(settings.stiffness, settings.damping, dt);
+
+        remaining -= dt;
+    }
+
+    physics.offset.settle(0.05);
+    physics.rotation.settle(0.05);
+    physics.scale.settle(0.0005);
+
+    data.requires_update = true;
+}
@@ -0,0 +1,108 @@
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr::{null, null_mut};
+use fontconfig_sys::{FcChar8, FcConfigSubstitute, FcDefaultSubstitute, FcFontMatch, FcMatchPattern, FcNameParse, FcPatternDestroy, FcPatternGetString, FcResultMatch, FcResultNoMatch};
+use fontconfig_sys::constants::FC_FILE;
+use sdl2::pixels::Color;
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+use sdl2_sys::{SDL_HINT_VIDEO_X11_NET_WM_BYPASS_COMPOSITOR, SDL_HINT_VIDEO_X11_WINDOW_VISUALID, SDL_SetHint};
+use x11::xlib::{False, TrueColor, XCloseDisplay, XDefaultScreen, XGetSelectionOwner, XInternAtom, XMatchVisualInfo, XOpenDisplay, XVisualInfo};
+use crate::platform::{set_opacity, WindowTransparency};
+
+// What the Windows build uses, fontconfig picks the closest thing when it isn't installed
+const FONT_NAME: &str = "Arial Narrow";
+
+/// Asks fontconfig for the file of whichever installed font matches best.
+pub fn find_font() -> Option<String> {
+    let name = CString::new(FONT_NAME).unwrap();
+
+    unsafe {
+        let pattern = FcNameParse(name.as_ptr() as *const FcChar8);
+        if pattern.is_null() {
+            return None;
+        }
+
+        FcConfigSubstitute(null_mut(), pattern, FcMatchPattern);
+        FcDefaultSubstitute(pattern);
+
+        let mut result = FcResultNoMatch;
+        let font = FcFontMatch(null_mut(), pattern, &mut result);
+        FcPatternDestroy(pattern);
+
+        if font.is_null() {
+            return None;
+        }
+
+        let mut file: *mut FcChar8 = null_mut();
+        let path = if FcPatternGetString(font, FC_FILE.as_ptr(), 0, &mut file) == FcResultMatch {
+            Some(CStr::from_ptr(file as *const c_char).to_string_lossy().into_owned())
+        } else {
+            None
+        };
+
+        // The file name belongs to the matched pattern, so it's copied out before this.
+        FcPatternDestroy(font);
+        path
+    }
+}
+
+/// Tells SDL to create its window with a 32 bit visual, has to happen before the window's created.
+///
+/// Only worth it with a compositor running, without one the alpha channel is just ignored.
+pub fn prepare_argb_visual(video_subsystem: &VideoSubsystem) -> bool {
+    if video_subsystem.current_video_driver() != "x11" {
+        return false;
+    }
+
+    unsafe {
+        let display = XOpenDisplay(null());
+        if display.is_null() {
+            return false;
+        }
+
+        let screen = XDefaultScreen(display);
+
+        // Compositing managers own this selection, per the EWMH spec.
+        let selection = CString::new(format!("_NET_WM_CM_S{}", screen)).unwrap();
+        let has_compositor = XGetSelectionOwner(display, XInternAtom(display, selection.as_ptr(), False)) != 0;
+
+        let mut visual_info: XVisualInfo = mem::zeroed();
+        let has_visual = XMatchVisualInfo(display, screen, 32, TrueColor, &mut visual_info) != 0;
+
+        XCloseDisplay(display);
+
+        if !has_compositor || !has_visual {
+            return false;
+        }
+
+        let visual_id = CString::new(visual_info.visualid.to_string()).unwrap();
+        SDL_SetHint(SDL_HINT_VIDEO_X11_WINDOW_VISUALID.as_ptr() as *const c_char, visual_id.as_ptr());
+        // SDL asks the compositor to skip its windows by default, which would throw away the alpha.
+        SDL_SetHint(SDL_HINT_VIDEO_X11_NET_WM_BYPASS_COMPOSITOR.as_ptr() as *const c_char, c"0".as_ptr());
+    }
+
+    true
+}
+
+/// A window with its own alpha channel, blended with the desktop by the compositor.
+pub struct ArgbWindow;
+
+impl WindowTransparency for ArgbWindow {
+    fn name(&self) -> &'static str {
+        "ARGB Visual"
+    }
+
+    fn apply(&mut self, window: &Window, _key_color: Color, alpha: f32) {
+        set_opacity(window, alpha);
+    }
+
+    fn clear_color(&self, _key_color: Color) -> Color {
+        Color::RGBA(0, 0, 0, 0)
+    }
+
+    fn uses_key_color(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,154 @@
+use sdl2::pixels::Color;
+use serde::{Deserialize, Serialize};
+use sdl2::surface::SurfaceRef;
+use sdl2::sys::SDL_WindowFlags::SDL_WINDOW_SHOWN;
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+use sdl2_sys::SDL_SetWindowOpacity;
+
+#[cfg(target_os = "windows")]
+const FALLBACK_FONT_PATH: &str = "C:/Windows/Fonts/arial.ttf";
+#[cfg(target_os = "macos")]
+const FALLBACK_FONT_PATH: &str = "/System/Library/Fonts/Supplemental/Arial Narrow.ttf";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const FALLBACK_FONT_PATH: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+#[cfg(target_os = "windows")]
+mod win32;
+#[cfg(target_os = "linux")]
+mod linux;
+mod shaped;
+
+/// Which kind of transparency to ask for, the window is created with it so it only changes after a restart.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TransparencyMode {
+    // Keying color on Windows, an alpha channel on Linux if there's a compositor
+    #[default]
+    Native,
+    // Cut out of the avatar's alpha, so everything around it is click-through
+    Shaped,
+}
+
+impl TransparencyMode {
+    pub const ALL: [TransparencyMode; 2] = [TransparencyMode::Native, TransparencyMode::Shaped];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransparencyMode::Native => "Native",
+            TransparencyMode::Shaped => "Shaped Window",
+        }
+    }
+}
+
+/// What the window's shape depends on, it's only cut out again once this changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameShape {
+    // The current timing's address, 0 without one
+    pub(crate) timing: usize,
+    pub(crate) bounce_offset: f64,
+    // The whole window has to stay clickable, while the properties or the border are showing
+    pub(crate) is_full: bool,
+}
+
+/// How the window gets see-through around the avatar, which depends on what the OS can do.
+pub trait WindowTransparency {
+    /// Shown in the properties, so it's clear why the edges look the way they do.
+    fn name(&self) -> &'static str;
+
+    /// Called whenever the keying color or the window's opacity changes.
+    fn apply(&mut self, window: &Window, key_color: Color, alpha: f32);
+
+    /// What the window gets cleared to before anything's drawn on it.
+    fn clear_color(&self, key_color: Color) -> Color;
+
+    /// Whether the keying color does anything, it isn't shown in the properties otherwise.
+    fn uses_key_color(&self) -> bool;
+
+    /// Gets every frame of the avatar right before it's presented.
+    fn update_frame(&mut self, _window: &Window, _frame: &SurfaceRef, _shape: FrameShape) {}
+}
+
+/// The last resort, the window's cleared to the keying color so capture software can key it out instead.
+#[cfg(not(target_os = "windows"))]
+pub struct KeyColorWindow;
+
+#[cfg(not(target_os = "windows"))]
+impl WindowTransparency for KeyColorWindow {
+    fn name(&self) -> &'static str {
+        "Keying Color"
+    }
+
+    fn apply(&mut self, window: &Window, _key_color: Color, alpha: f32) {
+        set_opacity(window, alpha);
+    }
+
+    fn clear_color(&self, key_color: Color) -> Color {
+        Color::RGBA(key_color.r, key_color.g, key_color.b, 255)
+    }
+
+    fn uses_key_color(&self) -> bool {
+        true
+    }
+}
+
+/// Finds the font the properties and captions are drawn with.
+pub fn find_font() -> String {
+    #[cfg(target_os = "windows")]
+    let path = win32::find_font();
+    #[cfg(target_os = "linux")]
+    let path = linux::find_font();
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    let path = Some(String::from(FALLBACK_FONT_PATH));
+
+    path.unwrap_or_else(|| {
+        eprintln!("Couldn't find a font, trying {}", FALLBACK_FONT_PATH);
+        String::from(FALLBACK_FONT_PATH)
+    })
+}
+
+pub fn set_opacity(window: &Window, alpha: f32) {
+    unsafe {
+        // Not every window manager supports this, the avatar just doesn't fade there.
+        SDL_SetWindowOpacity(window.raw(), alpha);
+    }
+}
+
+fn build_window(video_subsystem: &VideoSubsystem, title: &str, width: u32, height: u32) -> Window {
+    video_subsystem.window(title, width, height)
+        .position_centered()
+        .set_window_flags(SDL_WINDOW_SHOWN as u32)
+        .borderless()
+        .opengl()
+        .build()
+        .unwrap()
+}
+
+/// Creates the main window with the best kind of transparency available, or a shaped window if that's what was asked for.
+///
+/// Windows uses a layered window keyed on the keying color. Linux uses a 32 bit visual when a compositor is running,
+/// so the avatar gets real per-pixel alpha. Anything else falls back to an SDL shaped window cut out of the frame.
+pub fn create_window(video_subsystem: &VideoSubsystem, title: &str, width: u32, height: u32, mode: TransparencyMode) -> (Window, Box<dyn WindowTransparency>) {
+    if mode == TransparencyMode::Shaped {
+        if let Some(window) = shaped::create_shaped_window(video_subsystem, title, width, height) {
+            return (window, Box::new(shaped::ShapedWindow::new()));
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        (build_window(video_subsystem, title, width, height), Box::new(win32::LayeredWindow))
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        #[cfg(target_os = "linux")]
+        if linux::prepare_argb_visual(video_subsystem) {
+            return (build_window(video_subsystem, title, width, height), Box::new(linux::ArgbWindow));
+        }
+
+        match shaped::create_shaped_window(video_subsystem, title, width, height) {
+            Some(window) => (window, Box::new(shaped::ShapedWindow::new())),
+            None => (build_window(video_subsystem, title, width, height), Box::new(KeyColorWindow))
+        }
+    }
+}
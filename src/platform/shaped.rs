@@ -0,0 +1,97 @@
+use std::ffi::CString;
+use std::ptr::null_mut;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::surface::{Surface, SurfaceRef};
+use sdl2::sys::SDL_WindowFlags::{SDL_WINDOW_BORDERLESS, SDL_WINDOW_OPENGL, SDL_WINDOW_SHOWN};
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+use sdl2_sys::{SDL_CreateShapedWindow, SDL_SetWindowShape, SDL_WINDOWPOS_CENTERED_MASK, SDL_WindowShapeMode, SDL_WindowShapeParams, WindowShapeMode};
+use crate::platform::{FrameShape, set_opacity, WindowTransparency};
+
+pub fn create_shaped_window(video_subsystem: &VideoSubsystem, title: &str, width: u32, height: u32) -> Option<Window> {
+    let title = CString::new(title).unwrap();
+    let flags = SDL_WINDOW_SHOWN as u32 | SDL_WINDOW_BORDERLESS as u32 | SDL_WINDOW_OPENGL as u32;
+
+    unsafe {
+        let raw = SDL_CreateShapedWindow(title.as_ptr(), SDL_WINDOWPOS_CENTERED_MASK, SDL_WINDOWPOS_CENTERED_MASK, width, height, flags);
+
+        if raw.is_null() {
+            eprintln!("Failed to create a shaped window: {}", sdl2::get_error());
+            return None;
+        }
+
+        Some(Window::from_ll(video_subsystem.clone(), raw, null_mut()))
+    }
+}
+
+/// A window cut down to wherever the avatar isn't fully transparent, so the edges are hard but there's no keying color.
+pub struct ShapedWindow {
+    // Covers the whole window, for while the properties or the border are showing
+    full_shape: Option<Surface<'static>>,
+    // What the window was last cut out of, working out a new shape is too slow to do every frame
+    last_shape: Option<FrameShape>,
+    has_failed: bool,
+}
+
+impl ShapedWindow {
+    pub fn new() -> ShapedWindow {
+        ShapedWindow {
+            full_shape: None,
+            last_shape: None,
+            has_failed: false
+        }
+    }
+}
+
+impl WindowTransparency for ShapedWindow {
+    fn name(&self) -> &'static str {
+        "Shaped Window"
+    }
+
+    fn apply(&mut self, window: &Window, _key_color: Color, alpha: f32) {
+        set_opacity(window, alpha);
+    }
+
+    fn clear_color(&self, _key_color: Color) -> Color {
+        Color::RGBA(0, 0, 0, 0)
+    }
+
+    fn uses_key_color(&self) -> bool {
+        false
+    }
+
+    fn update_frame(&mut self, window: &Window, frame: &SurfaceRef, frame_shape: FrameShape) {
+        if self.last_shape == Some(frame_shape) {
+            return;
+        }
+
+        self.last_shape = Some(frame_shape);
+
+        let shape = if frame_shape.is_full {
+            let size = window.size();
+
+            if self.full_shape.as_ref().is_none_or(|shape| shape.size() != size) {
+                let mut shape = Surface::new(size.0, size.1, PixelFormatEnum::ARGB8888).unwrap();
+                shape.fill_rect(None, Color::RGBA(255, 255, 255, 255)).unwrap();
+                self.full_shape = Some(shape);
+            }
+
+            self.full_shape.as_ref().unwrap()
+        } else {
+            frame
+        };
+
+        let mut mode = SDL_WindowShapeMode {
+            mode: WindowShapeMode::ShapeModeBinarizeAlpha,
+            parameters: SDL_WindowShapeParams { binarizationCutoff: 1 }
+        };
+
+        unsafe {
+            // Only said once, it'd otherwise fail the same way every frame.
+            if SDL_SetWindowShape(window.raw(), shape.raw(), &mut mode) != 0 && !self.has_failed {
+                eprintln!("Failed to set the window shape: {}", sdl2::get_error());
+                self.has_failed = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use sdl2::pixels::Color;
+use sdl2::video::Window;
+use winsafe::{COLORREF, HWND};
+use winsafe::co::{GWLP, LWA, WS_EX};
+use winsafe::prelude::*;
+use crate::platform::WindowTransparency;
+
+const FONT_PATH: &str = "C:/Windows/Fonts/ARIALN.TTF";
+
+pub fn find_font() -> Option<String> {
+    Some(String::from(FONT_PATH))
+}
+
+/// A layered window, where every pixel matching the keying color is see-through.
+pub struct LayeredWindow;
+
+impl WindowTransparency for LayeredWindow {
+    fn name(&self) -> &'static str {
+        "Layered Window"
+    }
+
+    fn apply(&mut self, window: &Window, key_color: Color, alpha: f32) {
+        unsafe {
+            if let RawWindowHandle::Win32(handle) = window.raw_window_handle() {
+                let hwnd: HWND = Handle::from_ptr(handle.hwnd);
+
+                hwnd.SetWindowLongPtr(GWLP::EXSTYLE, hwnd.GetWindowLongPtr(GWLP::EXSTYLE) | (WS_EX::LAYERED.raw() as isize));
+                // Faded out while asleep, the keying color stays see-through either way.
+                let alpha = (alpha * 255.0) as u8;
+                hwnd.SetLayeredWindowAttributes(COLORREF::new(key_color.r, key_color.g, key_color.b), alpha, LWA::COLORKEY | LWA::ALPHA).unwrap();
+            }
+        }
+    }
+
+    fn clear_color(&self, key_color: Color) -> Color {
+        Color::RGBA(key_color.r, key_color.g, key_color.b, 0)
+    }
+
+    fn uses_key_color(&self) -> bool {
+        true
+    }
+}
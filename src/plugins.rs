@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use wasmi::{Caller, Config, Engine, Extern, Linker, Module, Store, TypedFunc};
+use wasmi::core::{F32, TrapCode};
+use crate::{current_level, current_timings, NANOS_TO_MILLIS, SharedData};
+use crate::captions::{hide_caption, show_caption};
+
+// The module plugins import their host functions from
+const HOST_MODULE: &str = "pngtuber";
+
+// Longest text or name a plugin can hand over, in bytes
+const MAX_STRING_LENGTH: u32 = 4096;
+
+/// Where WASM plugins get loaded from, and how much they're allowed to run each frame.
+///
+/// A plugin exports `on_frame(level: f32, pitch: f32, dt: f32, is_speaking: i32, expression: i32, timing: i32)`,
+/// which is called every frame, and can call these imports from the `pngtuber` module while it runs:
+/// `set_offset(x: f32, y: f32)`, `set_rotation(degrees: f32)`, `set_scale(scale: f32)`,
+/// `set_parameter(name_ptr: i32, name_len: i32, value: f32)`, `say(text_ptr: i32, text_len: i32)` and `clear_caption()`.
+/// Strings are UTF-8, read from the plugin's exported `memory`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PluginSettings {
+    // Every .wasm file in here is loaded on startup, empty to not load any
+    pub(crate) directory: String,
+    // Roughly how many instructions each plugin can run per frame before it gets cut off
+    pub(crate) fuel_per_frame: u64,
+}
+
+impl Default for PluginSettings {
+    fn default() -> Self {
+        PluginSettings {
+            directory: String::new(),
+            fuel_per_frame: 100_000
+        }
+    }
+}
+
+enum PluginCommand {
+    SetOffset(f32, f32),
+    SetRotation(f32),
+    SetScale(f32),
+    SetParameter(String, f32),
+    Say(String),
+    ClearCaption,
+}
+
+/// What a plugin's host functions can get at, commands are applied once its frame is done.
+struct PluginHost {
+    commands: Vec<PluginCommand>,
+}
+
+type FrameFunc = TypedFunc<(F32, F32, F32, i32, i32, i32), ()>;
+
+pub struct Plugin {
+    pub(crate) name: String,
+    store: Store<PluginHost>,
+    on_frame: FrameFunc,
+    // All the fuel ever added to the store, since wasmi only reports how much was used
+    fuel_added: u64,
+    // Ran out of fuel last frame
+    is_out_of_fuel: bool,
+    // Set once the plugin traps for anything other than running out of fuel, it isn't called again
+    pub(crate) error: Option<String>,
+}
+
+pub struct PluginState {
+    pub(crate) plugins: Vec<Plugin>,
+    // Set by plugins, applied on top of everything else moving the avatar
+    pub(crate) offset: (f64, f64),
+    pub(crate) rotation: f64,
+    pub(crate) scale: f64,
+    // Read by parameters with the plugin input, by the parameter's name
+    pub(crate) parameters: HashMap<String, f32>,
+}
+
+impl PluginState {
+    pub fn new() -> PluginState {
+        PluginState {
+            plugins: Vec::new(),
+            offset: (0.0, 0.0),
+            rotation: 0.0,
+            scale: 1.0,
+            parameters: HashMap::new()
+        }
+    }
+
+    /// Undoes everything plugins did to the avatar, for when one of them gets disabled.
+    fn reset_effects(&mut self) {
+        self.offset = (0.0, 0.0);
+        self.rotation = 0.0;
+        self.scale = 1.0;
+        self.parameters.clear();
+    }
+}
+
+fn read_string(caller: &Caller<'_, PluginHost>, pointer: i32, length: i32) -> Option<String> {
+    if pointer < 0 || length < 0 || (length as u32) > MAX_STRING_LENGTH {
+        return None;
+    }
+
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let bytes = memory.data(caller).get((pointer as usize)..((pointer as usize) + (length as usize)))?;
+
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+fn create_linker(engine: &Engine) -> Result<Linker<PluginHost>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+
+    // Floats come through as wasmi's own F32, which converts straight to an f32.
+    linker.func_wrap(HOST_MODULE, "set_offset", |mut caller: Caller<'_, PluginHost>, x: F32, y: F32| {
+        caller.data_mut().commands.push(PluginCommand::SetOffset(x.into(), y.into()));
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "set_rotation", |mut caller: Caller<'_, PluginHost>, degrees: F32| {
+        caller.data_mut().commands.push(PluginCommand::SetRotation(degrees.into()));
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "set_scale", |mut caller: Caller<'_, PluginHost>, scale: F32| {
+        caller.data_mut().commands.push(PluginCommand::SetScale(scale.into()));
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "set_parameter", |mut caller: Caller<'_, PluginHost>, pointer: i32, length: i32, value: F32| {
+        if let Some(name) = read_string(&caller, pointer, length) {
+            caller.data_mut().commands.push(PluginCommand::SetParameter(name, value.into()));
+        }
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "say", |mut caller: Caller<'_, PluginHost>, pointer: i32, length: i32| {
+        if let Some(text) = read_string(&caller, pointer, length) {
+            caller.data_mut().commands.push(PluginCommand::Say(text));
+        }
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "clear_caption", |mut caller: Caller<'_, PluginHost>| {
+        caller.data_mut().commands.push(PluginCommand::ClearCaption);
+    })?;
+
+    Ok(linker)
+}
+
+fn load_plugin(engine: &Engine, linker: &Linker<PluginHost>, path: &Path, fuel: u64) -> Result<Plugin, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let module = Module::new(engine, file).map_err(|err| err.to_string())?;
+
+    let mut store = Store::new(engine, PluginHost { commands: Vec::new() });
+    // The start function gets the same budget as a frame, in case it tries to run forever.
+    let fuel_added = fuel.max(1);
+    store.add_fuel(fuel_added).map_err(|err| err.to_string())?;
+
+    let instance = linker.instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(|err| err.to_string())?;
+
+    let on_frame = instance.get_typed_func::<(F32, F32, F32, i32, i32, i32), ()>(&store, "on_frame")
+        .map_err(|err| err.to_string())?;
+
+    Ok(Plugin {
+        name: path.file_stem().and_then(|name| name.to_str()).map(String::from).unwrap_or_default(),
+        store,
+        on_frame,
+        fuel_added,
+        is_out_of_fuel: false,
+        error: None
+    })
+}
+
+/// Loads every plugin in the plugin directory, only done on startup.
+pub fn load_plugins(data: &mut SharedData) {
+    if data.plugin.directory.is_empty() {
+        return;
+    }
+
+    let entries = match fs::read_dir(data.plugin.directory.as_str()) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Failed to read plugin directory {}: {}", data.plugin.directory, err);
+            return;
+        }
+    };
+
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+
+    let linker = match create_linker(&engine) {
+        Ok(linker) => linker,
+        Err(err) => {
+            eprintln!("Failed to set up plugins: {}", err);
+            return;
+        }
+    };
+
+    let mut paths: Vec<_> = entries.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("wasm")))
+        .collect();
+
+    // Plugins apply their commands in this order, so keep it the same every time.
+    paths.sort();
+
+    for path in paths {
+        match load_plugin(&engine, &linker, &path, data.plugin.fuel_per_frame) {
+            Ok(plugin) => data.plugin_state.plugins.push(plugin),
+            Err(err) => eprintln!("Failed to load plugin {}: {}", path.display(), err)
+        }
+    }
+}
+
+fn apply_commands(data: &mut SharedData, commands: Vec<PluginCommand>) {
+    for command in commands {
+        let state = &mut data.plugin_state;
+
+        match command {
+            PluginCommand::SetOffset(x, y) => state.offset = (x as f64, y as f64),
+            PluginCommand::SetRotation(degrees) => state.rotation = degrees as f64,
+            PluginCommand::SetScale(scale) => state.scale = scale as f64,
+            PluginCommand::SetParameter(name, value) => {
+                state.parameters.insert(name, value);
+            }
+            PluginCommand::Say(text) => show_caption(data, text.as_str()),
+            PluginCommand::ClearCaption => hide_caption(data)
+        }
+
+        data.requires_update = true;
+    }
+}
+
+/// Runs every plugin's frame, each topped up to its fuel budget first so a slow one just gets cut off.
+pub fn tick_plugins(data: &mut SharedData, nanos_since_last_frame: u128) {
+    if data.plugin_state.plugins.is_empty() {
+        return;
+    }
+
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+    let level = current_level(data);
+    let pitch = unsafe { (*data.audio_data).current_pitch };
+    let is_speaking = data.is_speaking as i32;
+    let expression = data.current_expression as i32;
+
+    let timings = current_timings(data);
+    let timing = match data.current_timing {
+        Some(current) => unsafe { (*timings).iter() }
+            .position(|timing| std::ptr::eq(timing, current))
+            .map_or(-1, |index| index as i32),
+        None => -1
+    };
+
+    let budget = data.plugin.fuel_per_frame.max(1);
+    let mut commands = Vec::new();
+    let mut has_failed = false;
+
+    for plugin in data.plugin_state.plugins.iter_mut() {
+        if plugin.error.is_some() {
+            continue;
+        }
+
+        let consumed = plugin.store.fuel_consumed().unwrap_or(0);
+        let remaining = plugin.fuel_added.saturating_sub(consumed);
+
+        if remaining < budget {
+            let _ = plugin.store.add_fuel(budget - remaining);
+            plugin.fuel_added += budget - remaining;
+        }
+
+        let result = plugin.on_frame.call(&mut plugin.store, (level.into(), pitch.into(), millis.into(), is_speaking, expression, timing));
+
+        let was_out_of_fuel = plugin.is_out_of_fuel;
+        plugin.is_out_of_fuel = false;
+
+        if let Err(trap) = result {
+            if matches!(trap.trap_code(), Some(TrapCode::OutOfFuel)) {
+                // Only said once, instead of every frame it keeps happening.
+                if !was_out_of_fuel {
+                    eprintln!("Plugin {} ran out of fuel", plugin.name);
+                }
+
+                plugin.is_out_of_fuel = true;
+            } else {
+                let error = format!("Plugin {} failed: {}", plugin.name, trap);
+                eprintln!("{}", error);
+                plugin.error = Some(error);
+
+                // Nothing from a plugin that's been disabled gets applied, not even this frame.
+                plugin.store.data_mut().commands.clear();
+                has_failed = true;
+            }
+        }
+
+        // Whatever it managed to queue before being cut off still counts.
+        commands.append(&mut plugin.store.data_mut().commands);
+    }
+
+    if has_failed {
+        data.plugin_state.reset_effects();
+        data.requires_update = true;
+    }
+
+    apply_commands(data, commands);
+}
@@ -0,0 +1,120 @@
+use sdl2::rect::Rect;
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData};
+use crate::bounce::{BounceCurve, BounceSettings};
+use crate::layers::LayerImage;
+use crate::transform::{avatar_pivot, avatar_transform, copy_transformed};
+
+/// What happens when the avatar window gets clicked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PokeSettings {
+    pub(crate) enabled: bool,
+    // Shown instead of the avatar while the poke lasts, or empty to only bounce
+    pub(crate) texture_path: String,
+    // How long the reaction sprite stays up, in milliseconds
+    pub(crate) duration: f32,
+    pub(crate) bounce: BounceSettings,
+}
+
+impl Default for PokeSettings {
+    fn default() -> Self {
+        PokeSettings {
+            enabled: false,
+            texture_path: String::new(),
+            duration: 600.0,
+            bounce: BounceSettings {
+                height: 32.0,
+                duration: 350.0,
+                curve: BounceCurve::EaseOutBack
+            }
+        }
+    }
+}
+
+pub struct PokeState {
+    pub(crate) image: Option<LayerImage<'static>>,
+    // Milliseconds since the last poke, None when there isn't one going
+    time: Option<f32>,
+}
+
+impl PokeState {
+    pub fn new() -> PokeState {
+        PokeState {
+            image: None,
+            time: None
+        }
+    }
+}
+
+pub fn reload_poke_image(data: &mut SharedData) {
+    if let Some(image) = &data.poke_state.image {
+        unsafe {
+            image.destroy();
+        }
+    }
+
+    data.poke_state.image = if data.poke.texture_path.is_empty() {
+        None
+    } else {
+        Some(LayerImage::load(data, data.poke.texture_path.as_str()))
+    };
+}
+
+pub fn start_poke(data: &mut SharedData) {
+    if !data.poke.enabled {
+        return;
+    }
+
+    data.poke_state.time = Some(0.0);
+    data.requires_update = true;
+}
+
+/// The extra vertical offset from the poke's bounce.
+pub fn poke_offset(data: &SharedData) -> f64 {
+    match data.poke_state.time {
+        Some(time) if time < data.poke.bounce.duration => data.poke.bounce.offset(time),
+        _ => 0.0
+    }
+}
+
+pub fn tick_poke(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let time = match data.poke_state.time {
+        Some(time) => time + ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32,
+        None => return
+    };
+
+    // Draw one more frame after it ends, so the avatar goes back to normal.
+    data.poke_state.time = if time >= data.poke.duration.max(data.poke.bounce.duration) {
+        None
+    } else {
+        Some(time)
+    };
+
+    data.requires_update = true;
+}
+
+/// Draws the reaction sprite in place of the avatar, returning false if there isn't one to show.
+pub unsafe fn render_poke(window_size: (u32, u32), data: &SharedData) -> bool {
+    let image = match (&data.poke_state.image, data.poke_state.time) {
+        (Some(image), Some(time)) if time < data.poke.duration => image,
+        _ => return false
+    };
+
+    let height_reduction = match data.current_timing {
+        Some(timing) => (*timing).height_reduction,
+        None => 0
+    };
+
+    let window_height = window_size.1 - (height_reduction as u32);
+    let height_percent = (window_height as f64) / (image.texture_surface.height() as f64);
+    let new_width = ((image.texture_surface.width() as f64) * height_percent) as i32;
+
+    let x = ((window_size.0 as i32) / 2) - (new_width / 2);
+    let y = (window_size.1 - window_height) as i32;
+    let dest = Rect::new(x, y, new_width as u32, window_height);
+
+    copy_transformed(data.pngtuber_canvas, &image.texture, None, dest, &avatar_transform(data), avatar_pivot(window_size, data));
+
+    true
+}
@@ -0,0 +1,364 @@
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use std::time::SystemTime;
+use rhai::{AST, CallFnOptions, Dynamic, Engine, Map, Scope};
+use crate::{current_expression, current_level, current_timings, NANOS_TO_MILLIS, SharedData, SpeechTiming, switch_expression};
+use crate::actions::{find_action, start_action};
+use crate::captions::{hide_caption, show_caption};
+
+// How often script files get checked for changes, in milliseconds
+const WATCH_INTERVAL: f32 = 1000.0;
+
+// Keeps a runaway script (infinite loops, etc.) from freezing the frame, per hook call.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// What scripts can see, refreshed before every hook call.
+#[derive(Default)]
+struct ScriptSnapshot {
+    level: f32,
+    pitch: f32,
+    expression: String,
+    timing: i64,
+    is_speaking: bool,
+}
+
+/// Queued up by scripts and applied once they're done, so they never touch SharedData directly.
+enum ScriptCommand {
+    SetExpression(String),
+    SetTiming(Option<usize>),
+    SetOffset(f64, f64),
+    SetRotation(f64),
+    SetScale(f64),
+    Say(String),
+    ClearCaption,
+    PlayAction(String),
+}
+
+pub struct Script {
+    pub(crate) path: String,
+    ast: Option<AST>,
+    // Bound to `this` in every hook, so scripts can keep their own state between calls
+    this: Dynamic,
+    modified: Option<SystemTime>,
+    // The last compile or runtime error, a script that failed isn't run again until it changes
+    pub(crate) error: Option<String>,
+}
+
+impl Script {
+    fn load(engine: &Engine, path: &str) -> Script {
+        let mut script = Script {
+            path: String::from(path),
+            ast: None,
+            this: Dynamic::from(Map::new()),
+            modified: None,
+            error: None
+        };
+
+        script.reload(engine);
+        script
+    }
+
+    fn reload(&mut self, engine: &Engine) {
+        self.ast = None;
+        self.error = None;
+        self.this = Dynamic::from(Map::new());
+        self.modified = fs::metadata(self.path.as_str()).and_then(|metadata| metadata.modified()).ok();
+
+        let source = match fs::read_to_string(self.path.as_str()) {
+            Ok(source) => source,
+            Err(err) => {
+                self.fail(format!("Failed to read script {}: {}", self.path, err));
+                return;
+            }
+        };
+
+        match engine.compile(source) {
+            Ok(ast) => self.ast = Some(ast),
+            Err(err) => self.fail(format!("Failed to compile script {}: {}", self.path, err))
+        }
+    }
+
+    fn fail(&mut self, error: String) {
+        eprintln!("{}", error);
+        self.error = Some(error);
+    }
+
+    fn has_changed(&self) -> bool {
+        let modified = fs::metadata(self.path.as_str()).and_then(|metadata| metadata.modified()).ok();
+        modified.is_some() && modified != self.modified
+    }
+
+    fn call(&mut self, engine: &Engine, name: &str, args: Vec<Dynamic>) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let ast = match &self.ast {
+            Some(ast) => ast,
+            None => return
+        };
+
+        // Hooks are all optional.
+        if !ast.iter_functions().any(|function| function.name == name) {
+            return;
+        }
+
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.this);
+
+        let result = engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, name, args);
+
+        if let Err(err) = result {
+            self.fail(format!("Script {} failed in {}: {}", self.path, name, err));
+        }
+    }
+}
+
+pub struct ScriptState {
+    engine: Engine,
+    pub(crate) scripts: Vec<Script>,
+    snapshot: Rc<RefCell<ScriptSnapshot>>,
+    commands: Rc<RefCell<Vec<ScriptCommand>>>,
+    watch_timer: f32,
+    // Key names pressed since the last tick
+    pub(crate) pending_hotkeys: Vec<String>,
+    last_expression: usize,
+    last_timing: Option<*const SpeechTiming<'static>>,
+    // Set by scripts, applied on top of everything else moving the avatar
+    pub(crate) offset: (f64, f64),
+    pub(crate) rotation: f64,
+    pub(crate) scale: f64,
+    // Holds the avatar on a timing of the current expression, no matter the level
+    pub(crate) timing_override: Option<usize>,
+}
+
+impl ScriptState {
+    pub fn new() -> ScriptState {
+        let snapshot = Rc::new(RefCell::new(ScriptSnapshot::default()));
+        let commands = Rc::new(RefCell::new(Vec::new()));
+
+        ScriptState {
+            engine: create_engine(&snapshot, &commands),
+            scripts: Vec::new(),
+            snapshot,
+            commands,
+            watch_timer: 0.0,
+            pending_hotkeys: Vec::new(),
+            last_expression: 0,
+            last_timing: None,
+            offset: (0.0, 0.0),
+            rotation: 0.0,
+            scale: 1.0,
+            timing_override: None
+        }
+    }
+
+    /// Undoes everything scripts did to the avatar, for when one of them stops running or starts over.
+    fn reset_effects(&mut self) {
+        self.offset = (0.0, 0.0);
+        self.rotation = 0.0;
+        self.scale = 1.0;
+        self.timing_override = None;
+    }
+}
+
+/// Scripts tend to mix up ints and floats, so take either.
+fn to_number(value: &Dynamic) -> f64 {
+    value.as_float().or_else(|_| value.as_int().map(|value| value as f64)).unwrap_or(0.0)
+}
+
+fn create_engine(snapshot: &Rc<RefCell<ScriptSnapshot>>, commands: &Rc<RefCell<Vec<ScriptCommand>>>) -> Engine {
+    let mut engine = Engine::new();
+
+    // Rhai can't touch files, processes or the network on its own, these just keep it from hogging the frame.
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(4096);
+    engine.set_max_array_size(1024);
+    engine.set_max_map_size(1024);
+
+    let state = snapshot.clone();
+    engine.register_fn("level", move || state.borrow().level as f64);
+    let state = snapshot.clone();
+    engine.register_fn("pitch", move || state.borrow().pitch as f64);
+    let state = snapshot.clone();
+    engine.register_fn("expression", move || state.borrow().expression.clone());
+    let state = snapshot.clone();
+    engine.register_fn("timing", move || state.borrow().timing);
+    let state = snapshot.clone();
+    engine.register_fn("is_speaking", move || state.borrow().is_speaking);
+
+    let queue = commands.clone();
+    engine.register_fn("set_expression", move |name: &str| queue.borrow_mut().push(ScriptCommand::SetExpression(String::from(name))));
+    let queue = commands.clone();
+    engine.register_fn("set_timing", move |index: i64| queue.borrow_mut().push(ScriptCommand::SetTiming(usize::try_from(index).ok())));
+    let queue = commands.clone();
+    engine.register_fn("set_offset", move |x: Dynamic, y: Dynamic| queue.borrow_mut().push(ScriptCommand::SetOffset(to_number(&x), to_number(&y))));
+    let queue = commands.clone();
+    engine.register_fn("set_rotation", move |degrees: Dynamic| queue.borrow_mut().push(ScriptCommand::SetRotation(to_number(&degrees))));
+    let queue = commands.clone();
+    engine.register_fn("set_scale", move |scale: Dynamic| queue.borrow_mut().push(ScriptCommand::SetScale(to_number(&scale))));
+    let queue = commands.clone();
+    engine.register_fn("say", move |text: &str| queue.borrow_mut().push(ScriptCommand::Say(String::from(text))));
+    let queue = commands.clone();
+    engine.register_fn("clear_caption", move || queue.borrow_mut().push(ScriptCommand::ClearCaption));
+    let queue = commands.clone();
+    engine.register_fn("play_action", move |name: &str| queue.borrow_mut().push(ScriptCommand::PlayAction(String::from(name))));
+
+    engine
+}
+
+/// Loads whichever scripts were added or removed since the last tick, and reloads the ones that changed on disk.
+fn sync_scripts(data: &mut SharedData, millis: f32) {
+    let state = &mut data.script_state;
+
+    let is_outdated = state.scripts.len() != data.scripts.len()
+        || state.scripts.iter().zip(data.scripts.iter()).any(|(script, path)| script.path != *path);
+
+    if is_outdated {
+        state.scripts = data.scripts.iter().map(|path| Script::load(&state.engine, path.as_str())).collect();
+        state.reset_effects();
+        data.requires_update = true;
+    }
+
+    state.watch_timer += millis;
+    if state.watch_timer < WATCH_INTERVAL {
+        return;
+    }
+
+    state.watch_timer = 0.0;
+
+    let mut has_reloaded = false;
+
+    for script in state.scripts.iter_mut() {
+        if script.has_changed() {
+            script.reload(&state.engine);
+            has_reloaded = true;
+        }
+    }
+
+    if has_reloaded {
+        state.reset_effects();
+        data.requires_update = true;
+    }
+}
+
+pub fn reload_script(data: &mut SharedData, index: usize) {
+    let state = &mut data.script_state;
+
+    if let Some(script) = state.scripts.get_mut(index) {
+        script.reload(&state.engine);
+        state.reset_effects();
+        data.requires_update = true;
+    }
+}
+
+fn call_hook(data: &mut SharedData, name: &str, args: Vec<Dynamic>) {
+    let state = &mut data.script_state;
+
+    let mut has_failed = false;
+
+    for script in state.scripts.iter_mut() {
+        let was_running = script.error.is_none();
+        let queued = state.commands.borrow().len();
+        script.call(&state.engine, name, args.clone());
+
+        if was_running && script.error.is_some() {
+            // Anything it queued before failing is dropped along with it.
+            state.commands.borrow_mut().truncate(queued);
+            has_failed = true;
+        }
+    }
+
+    // Whatever the failed script left behind shouldn't stick around now that it's stopped.
+    if has_failed {
+        state.reset_effects();
+        data.requires_update = true;
+    }
+}
+
+fn apply_commands(data: &mut SharedData) {
+    let commands: Vec<ScriptCommand> = data.script_state.commands.borrow_mut().drain(..).collect();
+
+    for command in commands {
+        match command {
+            ScriptCommand::SetExpression(name) => {
+                let target = unsafe { (*data.expressions).iter() }
+                    .position(|expression| expression.name.eq_ignore_ascii_case(name.as_str()));
+
+                match target {
+                    Some(index) => switch_expression(data, index),
+                    None => eprintln!("Unknown expression from a script: {}", name)
+                }
+            }
+            ScriptCommand::SetTiming(index) => data.script_state.timing_override = index,
+            ScriptCommand::SetOffset(x, y) => data.script_state.offset = (x, y),
+            ScriptCommand::SetRotation(degrees) => data.script_state.rotation = degrees,
+            ScriptCommand::SetScale(scale) => data.script_state.scale = scale,
+            ScriptCommand::Say(text) => show_caption(data, text.as_str()),
+            ScriptCommand::ClearCaption => hide_caption(data),
+            ScriptCommand::PlayAction(name) => match find_action(data, name.as_str()) {
+                Some(index) => start_action(data, index),
+                None => eprintln!("Unknown action from a script: {}", name)
+            }
+        }
+
+        data.requires_update = true;
+    }
+}
+
+fn timing_index(data: &SharedData) -> i64 {
+    let timings = current_timings(data);
+
+    match data.current_timing {
+        Some(current) => unsafe { (*timings).iter() }
+            .position(|timing| std::ptr::eq(timing, current))
+            .map_or(-1, |index| index as i64),
+        None => -1
+    }
+}
+
+/// Runs every script's hooks for this frame. A failing script just gets skipped, it never takes the render loop down with it.
+pub fn tick_scripts(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+
+    sync_scripts(data, millis);
+
+    if data.script_state.scripts.is_empty() {
+        data.script_state.pending_hotkeys.clear();
+        return;
+    }
+
+    let level = current_level(data);
+    let timing = timing_index(data);
+    let expression = unsafe { (*current_expression(data)).name.clone() };
+
+    *data.script_state.snapshot.borrow_mut() = ScriptSnapshot {
+        level,
+        pitch: unsafe { (*data.audio_data).current_pitch },
+        expression: expression.clone(),
+        timing,
+        is_speaking: data.is_speaking
+    };
+
+    let has_state_changed = data.script_state.last_expression != data.current_expression
+        || data.script_state.last_timing.map(|timing| timing as usize) != data.current_timing.map(|timing| timing as usize);
+
+    if has_state_changed {
+        data.script_state.last_expression = data.current_expression;
+        data.script_state.last_timing = data.current_timing;
+        call_hook(data, "on_state_change", vec![Dynamic::from(expression), Dynamic::from(timing)]);
+    }
+
+    let hotkeys: Vec<String> = data.script_state.pending_hotkeys.drain(..).collect();
+    for key in hotkeys {
+        call_hook(data, "on_hotkey", vec![Dynamic::from(key)]);
+    }
+
+    call_hook(data, "on_tick", vec![Dynamic::from(level as f64), Dynamic::from(millis as f64)]);
+
+    apply_commands(data);
+}
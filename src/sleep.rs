@@ -0,0 +1,147 @@
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData, switch_expression};
+
+/// Puts the avatar to sleep after a long silence, and optionally fades it out entirely.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SleepSettings {
+    pub(crate) enabled: bool,
+    // Switched to when falling asleep, empty to keep the current expression
+    pub(crate) expression: String,
+    // in minutes
+    pub(crate) sleep_after: f32,
+    pub(crate) fade_out: bool,
+    // in minutes
+    pub(crate) fade_after: f32,
+    // in milliseconds
+    pub(crate) fade_out_time: f32,
+    pub(crate) fade_in_time: f32,
+}
+
+impl Default for SleepSettings {
+    fn default() -> Self {
+        SleepSettings {
+            enabled: false,
+            expression: String::new(),
+            sleep_after: 5.0,
+            fade_out: false,
+            fade_after: 10.0,
+            fade_out_time: 3000.0,
+            fade_in_time: 300.0
+        }
+    }
+}
+
+pub struct SleepState {
+    // Set by tick_pngtuber whenever it's on a speaking timing
+    pub(crate) last_speech: Instant,
+    is_asleep: bool,
+    // What to go back to on waking up, if sleeping switched expressions
+    previous_expression: Option<usize>,
+    // How visible the window is, from 0 to 1
+    pub(crate) alpha: f32,
+    // The window's opacity has to be set again
+    pub(crate) is_alpha_dirty: bool,
+}
+
+impl SleepState {
+    pub fn new() -> SleepState {
+        SleepState {
+            last_speech: Instant::now(),
+            is_asleep: false,
+            previous_expression: None,
+            alpha: 1.0,
+            is_alpha_dirty: false
+        }
+    }
+}
+
+fn find_expression(data: &SharedData, name: &str) -> Option<usize> {
+    if name.is_empty() {
+        return None;
+    }
+
+    unsafe { (*data.expressions).iter() }
+        .position(|expression| expression.name.eq_ignore_ascii_case(name))
+}
+
+fn fall_asleep(data: &mut SharedData) {
+    data.sleep_state.is_asleep = true;
+
+    let target = find_expression(data, data.sleep.expression.as_str());
+    if let Some(index) = target {
+        if index != data.current_expression {
+            data.sleep_state.previous_expression = Some(data.current_expression);
+            switch_expression(data, index);
+        }
+    }
+}
+
+fn wake_up(data: &mut SharedData) {
+    data.sleep_state.is_asleep = false;
+
+    let previous = data.sleep_state.previous_expression.take();
+    let sleeping = find_expression(data, data.sleep.expression.as_str());
+
+    // Left alone if the expression got changed by hand while asleep.
+    if let Some(index) = previous {
+        if sleeping == Some(data.current_expression) {
+            switch_expression(data, index);
+        }
+    }
+}
+
+pub fn tick_sleep(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let millis = ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+
+    let target_alpha = if !data.sleep.enabled {
+        if data.sleep_state.is_asleep {
+            wake_up(data);
+        }
+
+        1.0
+    } else {
+        let silent_minutes = data.sleep_state.last_speech.elapsed().as_secs_f32() / 60.0;
+
+        if data.is_speaking {
+            if data.sleep_state.is_asleep {
+                wake_up(data);
+            }
+        } else if !data.sleep_state.is_asleep && silent_minutes >= data.sleep.sleep_after {
+            fall_asleep(data);
+        }
+
+        // Stays visible while the properties are open, otherwise they'd fade away mid edit.
+        if data.sleep.fade_out && !data.is_speaking && !data.is_props_open && silent_minutes >= data.sleep.fade_after {
+            0.0
+        } else {
+            1.0
+        }
+    };
+
+    let state = &mut data.sleep_state;
+    if state.alpha == target_alpha {
+        return;
+    }
+
+    let fade_time = if target_alpha < state.alpha {
+        data.sleep.fade_out_time
+    } else {
+        data.sleep.fade_in_time
+    };
+
+    state.alpha = if fade_time > 0.0 {
+        let step = millis / fade_time;
+
+        if target_alpha < state.alpha {
+            (state.alpha - step).max(target_alpha)
+        } else {
+            (state.alpha + step).min(target_alpha)
+        }
+    } else {
+        target_alpha
+    };
+
+    state.is_alpha_dirty = true;
+}
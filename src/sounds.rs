@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::SharedData;
+use crate::audio_output::{ensure_audio_output, read_sound};
+
+/// A sound file played when an expression or action starts, on the chosen output device.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SoundEffectSettings {
+    pub(crate) name: String,
+    // A WAV or OGG file
+    pub(crate) sound_path: String,
+    pub(crate) volume: f32,
+    // Plays when this expression is switched to, empty to never do that
+    pub(crate) expression: String,
+    // Plays when this action starts, empty to never do that
+    pub(crate) action: String,
+    // Counts towards the level the avatar reacts to, so it opens the mouth like speech would
+    pub(crate) affects_level: bool,
+}
+
+impl Default for SoundEffectSettings {
+    fn default() -> Self {
+        SoundEffectSettings {
+            name: String::from("Sound Effect"),
+            sound_path: String::new(),
+            volume: 1.0,
+            expression: String::new(),
+            action: String::new(),
+            affects_level: false
+        }
+    }
+}
+
+pub struct SoundEffect {
+    pub(crate) settings: SoundEffectSettings,
+    // Decoded once up front, so playing it doesn't stall a frame
+    samples: Option<Arc<Vec<f32>>>,
+    sample_rate: u32,
+}
+
+impl SoundEffect {
+    pub fn load(settings: &SoundEffectSettings) -> SoundEffect {
+        let mut effect = SoundEffect {
+            settings: settings.clone(),
+            samples: None,
+            sample_rate: 0
+        };
+
+        effect.reload_sound();
+        effect
+    }
+
+    pub fn reload_sound(&mut self) {
+        self.samples = None;
+
+        if self.settings.sound_path.is_empty() {
+            return;
+        }
+
+        match read_sound(self.settings.sound_path.as_str()) {
+            Ok((samples, sample_rate)) => {
+                self.samples = Some(Arc::new(samples));
+                self.sample_rate = sample_rate;
+            }
+            Err(err) => eprintln!("Failed to load sound {}: {}", self.settings.sound_path, err)
+        }
+    }
+}
+
+pub fn play_sound(data: &mut SharedData, index: usize) {
+    let (samples, sample_rate, volume, affects_level) = match data.sounds.get(index) {
+        Some(SoundEffect { samples: Some(samples), sample_rate, settings }) => (samples.clone(), *sample_rate, settings.volume, settings.affects_level),
+        _ => return
+    };
+
+    ensure_audio_output(data);
+
+    if let Some(output) = &data.audio_output {
+        output.play(samples, sample_rate, volume, affects_level);
+    }
+}
+
+pub fn play_sounds_for_expression(data: &mut SharedData, expression: &str) {
+    let matching: Vec<usize> = data.sounds.iter().enumerate()
+        .filter(|(_, sound)| !sound.settings.expression.is_empty() && sound.settings.expression.eq_ignore_ascii_case(expression))
+        .map(|(index, _)| index)
+        .collect();
+
+    for index in matching {
+        play_sound(data, index);
+    }
+}
+
+pub fn play_sounds_for_action(data: &mut SharedData, action: &str) {
+    let matching: Vec<usize> = data.sounds.iter().enumerate()
+        .filter(|(_, sound)| !sound.settings.action.is_empty() && sound.settings.action.eq_ignore_ascii_case(action))
+        .map(|(index, _)| index)
+        .collect();
+
+    for index in matching {
+        play_sound(data, index);
+    }
+}
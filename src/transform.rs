@@ -0,0 +1,165 @@
+use std::f64::consts::PI;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{Canvas, Texture};
+use sdl2::surface::Surface;
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData};
+use crate::actions::action_offset;
+use crate::idle::idle_motion;
+use crate::parameters::parameter_modifiers;
+use crate::poke::poke_offset;
+
+/// Per-timing scale, squash and stretch, and rotation wobble, for the "talking wiggle".
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WobbleSettings {
+    pub(crate) scale_x: f32,
+    pub(crate) scale_y: f32,
+    // How much taller (and thinner) the avatar gets at the peak of a squash, 0 disables it
+    pub(crate) squash: f32,
+    // in Hz
+    pub(crate) squash_speed: f32,
+    // in degrees, 0 disables it
+    pub(crate) wobble_angle: f32,
+    // in Hz
+    pub(crate) wobble_speed: f32,
+    // Fraction of the window size, 0.5 x 1.0 being the bottom middle
+    pub(crate) pivot_x: f32,
+    pub(crate) pivot_y: f32,
+}
+
+impl Default for WobbleSettings {
+    fn default() -> Self {
+        WobbleSettings {
+            scale_x: 1.0,
+            scale_y: 1.0,
+            squash: 0.0,
+            squash_speed: 4.0,
+            wobble_angle: 0.0,
+            wobble_speed: 3.0,
+            pivot_x: 0.5,
+            pivot_y: 1.0
+        }
+    }
+}
+
+impl WobbleSettings {
+    pub fn is_animated(&self) -> bool {
+        self.squash != 0.0 || self.wobble_angle != 0.0
+    }
+
+    /// Gets the squash and the rotation, at some time (in milliseconds) after the timing became active.
+    fn evaluate(&self, time: f32) -> (f64, f64) {
+        let secs = (time as f64) / 1000.0;
+        let squash = (self.squash as f64) * (2.0 * PI * (self.squash_speed as f64) * secs).sin();
+        let rotation = (self.wobble_angle as f64) * (2.0 * PI * (self.wobble_speed as f64) * secs).sin();
+
+        (squash, rotation)
+    }
+}
+
+/// Everything that moves the avatar as a whole, applied around a pivot point in the window.
+pub struct AvatarTransform {
+    pub(crate) offset_x: f64,
+    pub(crate) offset_y: f64,
+    pub(crate) scale_x: f64,
+    pub(crate) scale_y: f64,
+    // in degrees, clockwise
+    pub(crate) rotation: f64,
+}
+
+impl AvatarTransform {
+    pub fn identity() -> AvatarTransform {
+        AvatarTransform {
+            offset_x: 0.0,
+            offset_y: 0.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            rotation: 0.0
+        }
+    }
+
+    pub fn transform_rect(&self, dest: Rect, pivot: (f64, f64)) -> Rect {
+        // Flipping isn't supported, so don't let the scale go negative.
+        let scale_x = self.scale_x.max(0.01);
+        let scale_y = self.scale_y.max(0.01);
+
+        let x = pivot.0 + ((dest.x() as f64) - pivot.0) * scale_x + self.offset_x;
+        let y = pivot.1 + ((dest.y() as f64) - pivot.1) * scale_y + self.offset_y;
+        let width = ((dest.width() as f64) * scale_x).max(1.0);
+        let height = ((dest.height() as f64) * scale_y).max(1.0);
+
+        Rect::new(x.round() as i32, y.round() as i32, width.round() as u32, height.round() as u32)
+    }
+}
+
+/// Gets the transform for the avatar this frame, from the bounce, actions, pokes, spring physics, wobble, idle motion, scripts and plugins.
+pub fn avatar_transform(data: &SharedData) -> AvatarTransform {
+    let physics = &data.physics;
+    let spring_scale = 1.0 + (physics.scale.position as f64);
+    let (breathing, sway) = idle_motion(data);
+    let parameters = parameter_modifiers(data, "");
+    let script = &data.script_state;
+    let plugin = &data.plugin_state;
+
+    let (scale_x, scale_y, squash, wobble) = match data.current_timing {
+        Some(timing) => unsafe {
+            let wobble = &(*timing).wobble;
+            let (squash, rotation) = wobble.evaluate(data.wobble_time);
+
+            (wobble.scale_x as f64, wobble.scale_y as f64, squash, rotation)
+        },
+        None => (1.0, 1.0, 0.0, 0.0)
+    };
+
+    AvatarTransform {
+        offset_x: sway + parameters.offset_x + script.offset.0 + plugin.offset.0,
+        offset_y: data.current_velocity + action_offset(data) + poke_offset(data) + (physics.offset.position as f64) + parameters.offset_y + script.offset.1 + plugin.offset.1,
+        // Stretching up squeezes in, so it looks like the avatar keeps its volume.
+        scale_x: scale_x * (1.0 - squash) * spring_scale * parameters.scale * script.scale * plugin.scale,
+        scale_y: scale_y * (1.0 + squash) * spring_scale * breathing * parameters.scale * script.scale * plugin.scale,
+        rotation: (physics.rotation.position as f64) + wobble + parameters.rotation + script.rotation + plugin.rotation
+    }
+}
+
+/// The point the avatar scales and rotates around, the bottom middle of the window unless
+/// the current timing says otherwise.
+pub fn avatar_pivot(window_size: (u32, u32), data: &SharedData) -> (f64, f64) {
+    let (pivot_x, pivot_y) = match data.current_timing {
+        Some(timing) => unsafe { ((*timing).wobble.pivot_x as f64, (*timing).wobble.pivot_y as f64) },
+        None => (0.5, 1.0)
+    };
+
+    ((window_size.0 as f64) * pivot_x, (window_size.1 as f64) * pivot_y)
+}
+
+/// Keeps the renderer awake while the current timing is wobbling.
+pub fn tick_wobble(data: &mut SharedData, nanos_since_last_frame: u128) {
+    let timing = match data.current_timing {
+        Some(timing) => timing,
+        None => return
+    };
+
+    if !unsafe { (*timing).wobble.is_animated() } {
+        return;
+    }
+
+    data.wobble_time += ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+    data.requires_update = true;
+}
+
+pub unsafe fn copy_transformed(canvas: *mut Canvas<Surface<'static>>, texture: &Texture, source: Option<Rect>, dest: Rect, transform: &AvatarTransform, pivot: (f64, f64)) {
+    let transformed = transform.transform_rect(dest, pivot);
+
+    if transform.rotation == 0.0 {
+        (*canvas).copy(texture, source, Option::from(transformed)).unwrap();
+        return;
+    }
+
+    // copy_ex rotates around a point relative to the destination rect
+    let pivot_x = pivot.0 + transform.offset_x;
+    let pivot_y = pivot.1 + transform.offset_y;
+    let center = Point::new((pivot_x - (transformed.x() as f64)).round() as i32, (pivot_y - (transformed.y() as f64)).round() as i32);
+
+    (*canvas).copy_ex(texture, source, Option::from(transformed), transform.rotation, Option::from(center), false, false).unwrap();
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use crate::{NANOS_TO_MILLIS, SharedData};
+
+/// How a timing animates in when it becomes the current timing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TransitionSettings {
+    // in milliseconds, 0 snaps instantly like before
+    pub(crate) duration: f32,
+    pub(crate) crossfade: bool,
+    // Extra scale at the start of the transition, shrinking back down to 1x
+    pub(crate) pop: f32,
+    // Starting offset in pixels, sliding back to where the avatar normally is
+    pub(crate) slide_x: i32,
+    pub(crate) slide_y: i32,
+    pub(crate) tint: bool,
+    pub(crate) tint_color: [f32; 3],
+}
+
+impl Default for TransitionSettings {
+    fn default() -> Self {
+        TransitionSettings {
+            duration: 0.0,
+            crossfade: false,
+            pop: 0.0,
+            slide_x: 0,
+            slide_y: 0,
+            tint: false,
+            tint_color: [1.0, 1.0, 1.0]
+        }
+    }
+}
+
+pub struct TransitionEffect {
+    pub(crate) alpha: u8,
+    pub(crate) color: (u8, u8, u8),
+    pub(crate) scale: f64,
+    pub(crate) offset_x: i32,
+    pub(crate) offset_y: i32,
+}
+
+impl TransitionEffect {
+    pub fn none() -> TransitionEffect {
+        TransitionEffect {
+            alpha: 255,
+            color: (255, 255, 255),
+            scale: 1.0,
+            offset_x: 0,
+            offset_y: 0
+        }
+    }
+
+    pub fn faded(alpha: f32) -> TransitionEffect {
+        TransitionEffect {
+            alpha: (alpha.clamp(0.0, 1.0) * 255.0) as u8,
+            ..TransitionEffect::none()
+        }
+    }
+}
+
+impl TransitionSettings {
+    /// Gets the effect to apply to the incoming timing, with progress going from 0 to 1.
+    pub fn effect(&self, progress: f32) -> TransitionEffect {
+        if progress >= 1.0 {
+            return TransitionEffect::none();
+        }
+
+        // ease out, so the pop and slide settle gently
+        let remaining = (1.0 - progress) * (1.0 - progress);
+
+        let alpha = if self.crossfade {
+            progress
+        } else {
+            1.0
+        };
+
+        let color = if self.tint {
+            let lerp = |c: f32| ((c + (1.0 - c) * (1.0 - remaining)).clamp(0.0, 1.0) * 255.0) as u8;
+            (lerp(self.tint_color[0]), lerp(self.tint_color[1]), lerp(self.tint_color[2]))
+        } else {
+            (255, 255, 255)
+        };
+
+        TransitionEffect {
+            alpha: (alpha * 255.0) as u8,
+            color,
+            scale: 1.0 + (self.pop * remaining) as f64,
+            offset_x: ((self.slide_x as f32) * remaining).round() as i32,
+            offset_y: ((self.slide_y as f32) * remaining).round() as i32
+        }
+    }
+}
+
+/// Gets how far along the current timing's transition is, from 0 to 1.
+pub fn transition_progress(data: &SharedData) -> f32 {
+    let timing = match data.current_timing {
+        Some(timing) => timing,
+        None => return 1.0
+    };
+
+    let duration = unsafe { (*timing).transition.duration };
+    if duration <= 0.0 {
+        return 1.0;
+    }
+
+    (data.transition_time / duration).min(1.0)
+}
+
+/// Jumps to the end of whatever transition is playing, for when the timing it fades from might not exist anymore.
+pub fn finish_transition(data: &mut SharedData) {
+    data.previous_timing = None;
+    data.transition_time = f32::MAX;
+}
+
+/// Keeps the renderer awake while a transition is playing, and lets it sleep again after.
+pub fn tick_transition(data: &mut SharedData, nanos_since_last_frame: u128) {
+    if transition_progress(data) >= 1.0 {
+        return;
+    }
+
+    data.transition_time += ((nanos_since_last_frame as f64) / NANOS_TO_MILLIS) as f32;
+    data.requires_update = true;
+
+    if transition_progress(data) >= 1.0 {
+        data.previous_timing = None;
+    }
+}
@@ -0,0 +1,180 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::SharedData;
+use crate::audio_output::{ensure_audio_output, read_wav};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TtsEngine {
+    EspeakNg,
+    Piper,
+}
+
+impl TtsEngine {
+    pub const ALL: [TtsEngine; 2] = [TtsEngine::EspeakNg, TtsEngine::Piper];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TtsEngine::EspeakNg => "eSpeak NG",
+            TtsEngine::Piper => "Piper",
+        }
+    }
+
+    fn default_binary(&self) -> &'static str {
+        match self {
+            TtsEngine::EspeakNg => "espeak-ng",
+            TtsEngine::Piper => "piper",
+        }
+    }
+}
+
+/// Speaks text through a local TTS engine, which the avatar then lip syncs to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TtsSettings {
+    pub(crate) enabled: bool,
+    pub(crate) engine: TtsEngine,
+    // Empty looks for the engine on the PATH
+    pub(crate) binary_path: String,
+    // An eSpeak voice name, or the path to a Piper model
+    pub(crate) voice: String,
+    // 1 is the engine's normal speed
+    pub(crate) speed: f32,
+    pub(crate) volume: f32,
+}
+
+impl Default for TtsSettings {
+    fn default() -> Self {
+        TtsSettings {
+            enabled: false,
+            engine: TtsEngine::EspeakNg,
+            binary_path: String::new(),
+            voice: String::new(),
+            speed: 1.0,
+            volume: 1.0
+        }
+    }
+}
+
+// Mono samples, and their sample rate
+type Synthesized = Result<(Vec<f32>, u32), String>;
+
+pub struct TtsState {
+    pending: Option<Receiver<Synthesized>>,
+}
+
+impl TtsState {
+    pub fn new() -> TtsState {
+        TtsState {
+            pending: None
+        }
+    }
+}
+
+fn synthesize(settings: &TtsSettings, text: &str) -> Synthesized {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos()).unwrap_or(0);
+    let wav_path = env::temp_dir().join(format!("pngtuber_tts_{}.wav", nanos));
+    let wav_path_str = wav_path.to_str().ok_or("Invalid temp path")?;
+
+    let binary = if settings.binary_path.is_empty() {
+        settings.engine.default_binary()
+    } else {
+        settings.binary_path.as_str()
+    };
+
+    let mut command = Command::new(binary);
+
+    match settings.engine {
+        TtsEngine::EspeakNg => {
+            command.args(["--stdin", "-w", wav_path_str, "-s"]).arg(((175.0 * settings.speed.max(0.1)) as i32).to_string());
+
+            if !settings.voice.is_empty() {
+                command.args(["-v", settings.voice.as_str()]);
+            }
+        }
+        TtsEngine::Piper => {
+            command.args(["--model", settings.voice.as_str(), "--output_file", wav_path_str, "--length_scale"])
+                .arg((1.0 / settings.speed.max(0.1)).to_string());
+        }
+    }
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("Failed to start {}: {}", binary, err))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).map_err(|err| err.to_string())?;
+    }
+
+    let status = child.wait().map_err(|err| err.to_string())?;
+    if !status.success() {
+        let _ = fs::remove_file(&wav_path);
+        return Err(format!("{} exited with {}", binary, status));
+    }
+
+    let decoded = read_wav(wav_path_str);
+    let _ = fs::remove_file(&wav_path);
+
+    decoded
+}
+
+/// Synthesizes the text in the background, it gets played once it's ready.
+pub fn start_tts(data: &mut SharedData, text: &str) {
+    let settings = data.tts.clone();
+    let text = String::from(text);
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        let _ = sender.send(synthesize(&settings, text.as_str()));
+    });
+
+    // Anything still synthesizing gets dropped, the newest text wins.
+    data.tts_state.pending = Some(receiver);
+}
+
+pub fn stop_tts(data: &mut SharedData) {
+    data.tts_state.pending = None;
+
+    if let Some(output) = &data.audio_output {
+        output.stop_speech();
+    }
+}
+
+pub fn tick_tts(data: &mut SharedData) {
+    let received = match &data.tts_state.pending {
+        Some(receiver) => receiver.try_recv(),
+        None => return
+    };
+
+    let result = match received {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return,
+        Err(TryRecvError::Disconnected) => {
+            data.tts_state.pending = None;
+            return;
+        }
+    };
+
+    data.tts_state.pending = None;
+
+    match result {
+        Ok((samples, sample_rate)) => {
+            ensure_audio_output(data);
+
+            if let Some(output) = &data.audio_output {
+                output.stop_speech();
+                output.play_speech(Arc::new(samples), sample_rate, data.tts.volume);
+            }
+        }
+        Err(err) => eprintln!("Failed to synthesize speech: {}", err)
+    }
+}
@@ -0,0 +1,120 @@
+use std::cell::Cell;
+use rand::Rng;
+use sdl2::render::Texture;
+use sdl2::surface::Surface;
+use sdl2_sys::SDL_DestroyTexture;
+use serde::{Deserialize, Serialize};
+use crate::{load_image, SharedData};
+use crate::animation::{Animation, AnimationSettings};
+
+/// How a timing with several images picks which one to show each time it's entered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum VariantMode {
+    #[default]
+    Random,
+    RoundRobin,
+    Weighted,
+}
+
+impl VariantMode {
+    pub const ALL: [VariantMode; 3] = [VariantMode::Random, VariantMode::RoundRobin, VariantMode::Weighted];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VariantMode::Random => "Random",
+            VariantMode::RoundRobin => "Round Robin",
+            VariantMode::Weighted => "Weighted",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ImageVariant {
+    pub(crate) texture_path: String,
+    // Only used by the weighted mode, relative to the other images
+    pub(crate) weight: f32,
+}
+
+impl Default for ImageVariant {
+    fn default() -> Self {
+        ImageVariant {
+            texture_path: String::new(),
+            weight: 1.0
+        }
+    }
+}
+
+pub struct TimingImage<'a> {
+    pub(crate) texture_path: String,
+    pub(crate) weight: f32,
+    pub(crate) texture_surface: Surface<'a>,
+    pub(crate) texture: Texture,
+    pub(crate) animation: Option<Animation<'a>>,
+}
+
+impl TimingImage<'_> {
+    pub fn load(data: &SharedData, variant: &ImageVariant, animation_settings: &AnimationSettings) -> TimingImage<'static> {
+        let (png_surface, png_texture) = load_image(data, variant.texture_path.as_str());
+
+        TimingImage {
+            texture_path: variant.texture_path.clone(),
+            weight: variant.weight,
+            texture_surface: png_surface,
+            texture: png_texture,
+            animation: Animation::load(data, variant.texture_path.as_str(), animation_settings)
+        }
+    }
+
+    pub fn save(&self) -> ImageVariant {
+        ImageVariant {
+            texture_path: self.texture_path.clone(),
+            weight: self.weight
+        }
+    }
+
+    pub unsafe fn destroy(&self) {
+        drop(self.texture_surface.context());
+        // this works better than the Rust destroy because Rust is too safe.
+        SDL_DestroyTexture(self.texture.raw());
+
+        if let Some(animation) = &self.animation {
+            animation.destroy();
+        }
+    }
+}
+
+/// Picks the next image to show, out of however many the timing has.
+pub fn pick_variant(mode: VariantMode, images: &[TimingImage], next_image: &Cell<usize>) -> usize {
+    if images.len() <= 1 {
+        return 0;
+    }
+
+    let mut rng = rand::thread_rng();
+
+    match mode {
+        VariantMode::Random => rng.gen_range(0..images.len()),
+        VariantMode::RoundRobin => {
+            let index = next_image.get() % images.len();
+            next_image.set(index + 1);
+            index
+        }
+        VariantMode::Weighted => {
+            let total: f32 = images.iter().map(|image| image.weight.max(0.0)).sum();
+            if total <= 0.0 {
+                return rng.gen_range(0..images.len());
+            }
+
+            let mut roll = rng.gen_range(0.0..total);
+            for (index, image) in images.iter().enumerate() {
+                roll -= image.weight.max(0.0);
+
+                if roll < 0.0 {
+                    return index;
+                }
+            }
+
+            images.len() - 1
+        }
+    }
+}